@@ -0,0 +1,884 @@
+use crate::bus::OPEN_BUS;
+use crate::error::Result;
+use alloc::vec::Vec;
+use bitmatch::bitmatch;
+use serde::{Deserialize, Serialize};
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CLOCK: u32 = 4194304;
+const FRAME_SEQUENCER_PERIOD: u16 = 8192;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// [-1.0, 1.0] の範囲外はクランプする
+pub fn sample_to_i16(sample: f32) -> i16 {
+    let val = sample.clamp(-1.0, 1.0) * i16::MAX as f32;
+
+    // no_stdではf32::roundが使えないため、0から遠い方へ丸める
+    if val >= 0.0 {
+        (val + 0.5) as i16
+    } else {
+        (val - 0.5) as i16
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelState {
+    pub frequency: f32,
+    pub volume: u8,
+    pub on: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Envelope {
+    initial_volume: u8,
+    add: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    #[bitmatch]
+    fn write(&mut self, val: u8) {
+        #[bitmatch]
+        let "vvvvappp" = val;
+
+        self.initial_volume = v;
+        self.add = a == 1;
+        self.period = p;
+    }
+
+    #[bitmatch]
+    fn read(&self) -> u8 {
+        let v = self.initial_volume;
+        let a = self.add;
+        let p = self.period;
+
+        bitpack!("vvvvappp")
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.add
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn step(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.add && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.add && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct SquareChannel {
+    enabled: bool,
+    duty: u8,
+    duty_pos: u8,
+    length: u8,
+    length_enable: bool,
+    frequency: u16,
+    timer: u16,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    fn with_sweep() -> Self {
+        Self {
+            sweep: Some(Default::default()),
+            ..Default::default()
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency) * 4;
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        DUTY_PATTERNS[self.duty as usize][self.duty_pos as usize] * self.envelope.volume
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn calc_sweep(&mut self) -> u16 {
+        let sweep = match self.sweep.as_mut() {
+            Some(sweep) => sweep,
+            None => return self.frequency,
+        };
+
+        let delta = sweep.shadow >> sweep.shift;
+        let result = if sweep.negate {
+            sweep.shadow.wrapping_sub(delta)
+        } else {
+            sweep.shadow + delta
+        };
+
+        if result > 2047 {
+            self.enabled = false;
+        }
+
+        result
+    }
+
+    fn step_sweep(&mut self) {
+        let (period, shift, enabled) = match self.sweep.as_mut() {
+            Some(sweep) => {
+                if sweep.timer > 0 {
+                    sweep.timer -= 1;
+                }
+
+                if sweep.timer != 0 {
+                    return;
+                }
+
+                sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+
+                (sweep.period, sweep.shift, sweep.enabled)
+            }
+            None => return,
+        };
+
+        if !enabled || period == 0 {
+            return;
+        }
+
+        let frequency = self.calc_sweep();
+
+        if frequency <= 2047 && shift != 0 {
+            self.frequency = frequency;
+
+            if let Some(sweep) = self.sweep.as_mut() {
+                sweep.shadow = frequency;
+            }
+
+            self.calc_sweep();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.timer = (2048 - self.frequency) * 4;
+        self.envelope.trigger();
+
+        let frequency = self.frequency;
+
+        let shift = match self.sweep.as_mut() {
+            Some(sweep) => {
+                sweep.shadow = frequency;
+                sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+                sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+                sweep.shift
+            }
+            None => return,
+        };
+
+        if shift != 0 {
+            self.calc_sweep();
+        }
+    }
+
+    fn state(&self) -> ChannelState {
+        ChannelState {
+            frequency: 131072.0 / (2048 - self.frequency) as f32,
+            volume: self.envelope.volume,
+            on: self.enabled,
+        }
+    }
+
+    #[bitmatch]
+    fn read_sweep(&self) -> u8 {
+        match self.sweep.as_ref() {
+            Some(sweep) => {
+                let p = sweep.period;
+                let n = sweep.negate;
+                let s = sweep.shift;
+
+                bitpack!("1pppnsss")
+            }
+            None => 0xFF,
+        }
+    }
+
+    #[bitmatch]
+    fn write_sweep(&mut self, val: u8) {
+        #[bitmatch]
+        let "?pppnsss" = val;
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.period = p;
+            sweep.negate = n == 1;
+            sweep.shift = s;
+        }
+    }
+
+    fn read_length_duty(&self) -> u8 {
+        (self.duty << 6) | 0x3F
+    }
+
+    #[bitmatch]
+    fn write_length_duty(&mut self, val: u8) {
+        #[bitmatch]
+        let "ddllllll" = val;
+
+        self.duty = d;
+        self.length = 64 - l;
+    }
+
+    fn write_envelope(&mut self, val: u8) {
+        self.envelope.write(val);
+
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x0700) | val as u16;
+    }
+
+    fn read_control(&self) -> u8 {
+        ((self.length_enable as u8) << 6) | 0xBF
+    }
+
+    #[bitmatch]
+    fn write_control(&mut self, val: u8) {
+        #[bitmatch]
+        let "tl???fff" = val;
+
+        self.frequency = (self.frequency & 0x00FF) | ((f as u16) << 8);
+        self.length_enable = l == 1;
+
+        if t == 1 {
+            self.trigger();
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: u16,
+    length_enable: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u16,
+    position: u8,
+    ram: [u8; 0x10],
+}
+
+impl WaveChannel {
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency) * 2;
+            self.position = (self.position + 1) % 32;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+
+        let sample = self.ram[(self.position / 2) as usize];
+        let sample = if self.position & 1 == 0 {
+            sample >> 4
+        } else {
+            sample & 0x0F
+        };
+
+        sample >> (self.volume_code - 1)
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+
+        if self.length == 0 {
+            self.length = 256;
+        }
+
+        self.timer = (2048 - self.frequency) * 2;
+        self.position = 0;
+    }
+
+    fn state(&self) -> ChannelState {
+        ChannelState {
+            frequency: 65536.0 / (2048 - self.frequency) as f32,
+            volume: match self.volume_code {
+                0 => 0,
+                code => 15 >> (code - 1),
+            },
+            on: self.enabled,
+        }
+    }
+
+    fn read_dac(&self) -> u8 {
+        ((self.dac_enabled as u8) << 7) | 0x7F
+    }
+
+    fn write_dac(&mut self, val: u8) {
+        self.dac_enabled = val & 0x80 > 0;
+
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn write_length(&mut self, val: u8) {
+        self.length = 256 - val as u16;
+    }
+
+    fn read_volume(&self) -> u8 {
+        (self.volume_code << 5) | 0x9F
+    }
+
+    fn write_volume(&mut self, val: u8) {
+        self.volume_code = (val >> 5) & 0b11;
+    }
+
+    fn write_frequency_low(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x0700) | val as u16;
+    }
+
+    fn read_control(&self) -> u8 {
+        ((self.length_enable as u8) << 6) | 0xBF
+    }
+
+    #[bitmatch]
+    fn write_control(&mut self, val: u8) {
+        #[bitmatch]
+        let "tl???fff" = val;
+
+        self.frequency = (self.frequency & 0x00FF) | ((f as u16) << 8);
+        self.length_enable = l == 1;
+
+        if t == 1 {
+            self.trigger();
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct NoiseChannel {
+    enabled: bool,
+    length: u8,
+    length_enable: bool,
+    envelope: Envelope,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift;
+
+            let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+
+            if self.width_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 > 0 {
+            return 0;
+        }
+
+        self.envelope.volume
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.timer = NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift;
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn state(&self) -> ChannelState {
+        let divisor = match self.divisor_code {
+            0 => 0.5,
+            code => code as f32,
+        };
+
+        ChannelState {
+            frequency: 524288.0 / divisor / (1 << (self.clock_shift + 1)) as f32,
+            volume: self.envelope.volume,
+            on: self.enabled,
+        }
+    }
+
+    fn write_length(&mut self, val: u8) {
+        self.length = 64 - (val & 0x3F);
+    }
+
+    fn write_envelope(&mut self, val: u8) {
+        self.envelope.write(val);
+
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    #[bitmatch]
+    fn read_polynomial(&self) -> u8 {
+        let s = self.clock_shift;
+        let w = self.width_mode;
+        let d = self.divisor_code;
+
+        bitpack!("sssswddd")
+    }
+
+    #[bitmatch]
+    fn write_polynomial(&mut self, val: u8) {
+        #[bitmatch]
+        let "sssswddd" = val;
+
+        self.clock_shift = s;
+        self.width_mode = w == 1;
+        self.divisor_code = d;
+    }
+
+    fn read_control(&self) -> u8 {
+        ((self.length_enable as u8) << 6) | 0xBF
+    }
+
+    #[bitmatch]
+    fn write_control(&mut self, val: u8) {
+        #[bitmatch]
+        let "tl??????" = val;
+
+        self.length_enable = l == 1;
+
+        if t == 1 {
+            self.trigger();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApuState {
+    power: bool,
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    master_volume: u8,
+    panning: u8,
+    frame_sequencer_cycles: u16,
+    frame_sequencer_step: u8,
+    sample_cycles: u32,
+}
+
+pub struct Apu {
+    power: bool,
+
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+
+    // ユーザによるミュート/ソロ用のマスク
+    channel_enabled: [bool; 4],
+
+    master_volume: u8,
+    panning: u8,
+
+    frame_sequencer_cycles: u16,
+    frame_sequencer_step: u8,
+
+    sample_rate: u32,
+    sample_cycles: u32,
+    buffer: Vec<f32>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            power: false,
+            channel1: SquareChannel::with_sweep(),
+            channel2: Default::default(),
+            channel3: Default::default(),
+            channel4: Default::default(),
+            channel_enabled: [true; 4],
+            master_volume: 0,
+            panning: 0,
+            frame_sequencer_cycles: 0,
+            frame_sequencer_step: 0,
+            sample_rate: SAMPLE_RATE,
+            sample_cycles: 0,
+            buffer: Vec::with_capacity(SAMPLE_RATE as usize * 2),
+        }
+    }
+}
+
+impl Apu {
+    pub fn tick(&mut self) {
+        if self.power {
+            self.channel1.tick();
+            self.channel2.tick();
+            self.channel3.tick();
+            self.channel4.tick();
+
+            self.frame_sequencer_cycles += 1;
+
+            if self.frame_sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_cycles = 0;
+                self.step_frame_sequencer();
+            }
+        }
+
+        self.sample_cycles += self.sample_rate;
+
+        if self.sample_cycles >= CLOCK {
+            self.sample_cycles -= CLOCK;
+
+            // 読み出されないまま溜まり続けないよう、1秒分を超えた分は捨てる
+            if self.buffer.len() < self.sample_rate as usize * 2 {
+                let (left, right) = self.mix();
+
+                self.buffer.push(left);
+                self.buffer.push(right);
+            }
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => {
+                self.step_length();
+            }
+            2 | 6 => {
+                self.step_length();
+                self.channel1.step_sweep();
+            }
+            7 => {
+                self.channel1.envelope.step();
+                self.channel2.envelope.step();
+                self.channel4.envelope.step();
+            }
+            _ => {}
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn step_length(&mut self) {
+        self.channel1.step_length();
+        self.channel2.step_length();
+        self.channel3.step_length();
+        self.channel4.step_length();
+    }
+
+    fn mix(&self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+
+        let outputs = [
+            self.channel1.output(),
+            self.channel2.output(),
+            self.channel3.output(),
+            self.channel4.output(),
+        ];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        for (i, &output) in outputs.iter().enumerate() {
+            if !self.channel_enabled[i] {
+                continue;
+            }
+
+            let sample = output as f32 / 15.0;
+
+            if self.panning & (0x10 << i) > 0 {
+                left += sample;
+            }
+
+            if self.panning & (0x01 << i) > 0 {
+                right += sample;
+            }
+        }
+
+        let left_volume = (((self.master_volume >> 4) & 0b111) + 1) as f32 / 8.0;
+        let right_volume = ((self.master_volume & 0b111) + 1) as f32 / 8.0;
+
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+
+    // ミュート設定、サンプルレート、未読み出しのサンプルは含まない
+    pub fn snapshot(&self) -> ApuState {
+        ApuState {
+            power: self.power,
+            channel1: self.channel1.clone(),
+            channel2: self.channel2.clone(),
+            channel3: self.channel3.clone(),
+            channel4: self.channel4.clone(),
+            master_volume: self.master_volume,
+            panning: self.panning,
+            frame_sequencer_cycles: self.frame_sequencer_cycles,
+            frame_sequencer_step: self.frame_sequencer_step,
+            sample_cycles: self.sample_cycles,
+        }
+    }
+
+    pub fn restore(&mut self, state: &ApuState) {
+        self.power = state.power;
+        self.channel1 = state.channel1.clone();
+        self.channel2 = state.channel2.clone();
+        self.channel3 = state.channel3.clone();
+        self.channel4 = state.channel4.clone();
+        self.master_volume = state.master_volume;
+        self.panning = state.panning;
+        self.frame_sequencer_cycles = state.frame_sequencer_cycles;
+        self.frame_sequencer_step = state.frame_sequencer_step;
+        self.sample_cycles = state.sample_cycles % CLOCK;
+        self.buffer.clear();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // 1サイクルに2サンプル以上は生成できないため、CPUクロックを上限とする
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, CLOCK);
+        self.sample_cycles = 0;
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.buffer.drain(..).collect()
+    }
+
+    pub fn drain_i16(&mut self) -> Vec<i16> {
+        self.buffer.drain(..).map(sample_to_i16).collect()
+    }
+
+    pub fn set_channel_enabled(&mut self, ch: u8, on: bool) {
+        if let Some(enabled) = self.channel_enabled.get_mut((ch as usize).wrapping_sub(1)) {
+            *enabled = on;
+        }
+    }
+
+    pub fn channel_enabled(&self, ch: u8) -> bool {
+        self.channel_enabled
+            .get((ch as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    pub fn channel_state(&self, ch: u8) -> Option<ChannelState> {
+        match ch {
+            1 => Some(self.channel1.state()),
+            2 => Some(self.channel2.state()),
+            3 => Some(self.channel3.state()),
+            4 => Some(self.channel4.state()),
+            _ => None,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> Result<u8> {
+        match addr {
+            0xFF10 => Ok(self.channel1.read_sweep()),
+            0xFF11 => Ok(self.channel1.read_length_duty()),
+            0xFF12 => Ok(self.channel1.envelope.read()),
+            0xFF14 => Ok(self.channel1.read_control()),
+            0xFF16 => Ok(self.channel2.read_length_duty()),
+            0xFF17 => Ok(self.channel2.envelope.read()),
+            0xFF19 => Ok(self.channel2.read_control()),
+            0xFF1A => Ok(self.channel3.read_dac()),
+            0xFF1C => Ok(self.channel3.read_volume()),
+            0xFF1E => Ok(self.channel3.read_control()),
+            0xFF21 => Ok(self.channel4.envelope.read()),
+            0xFF22 => Ok(self.channel4.read_polynomial()),
+            0xFF23 => Ok(self.channel4.read_control()),
+            0xFF24 => Ok(self.master_volume),
+            0xFF25 => Ok(self.panning),
+            0xFF26 => Ok(self.read_power()),
+            0xFF30..=0xFF3F => Ok(self.channel3.ram[(addr - 0xFF30) as usize]),
+            _ => Ok(OPEN_BUS),
+        }
+    }
+
+    #[bitmatch]
+    #[allow(clippy::many_single_char_names)]
+    fn read_power(&self) -> u8 {
+        let p = self.power;
+        let d = self.channel4.enabled;
+        let c = self.channel3.enabled;
+        let b = self.channel2.enabled;
+        let a = self.channel1.enabled;
+
+        bitpack!("p111dcba")
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) -> Result<()> {
+        if addr == 0xFF26 {
+            self.write_power(val);
+
+            return Ok(());
+        }
+
+        if let 0xFF30..=0xFF3F = addr {
+            self.channel3.ram[(addr - 0xFF30) as usize] = val;
+
+            return Ok(());
+        }
+
+        // 電源が切れている間は NR52 と波形メモリ以外への書き込みは無視される
+        if !self.power {
+            return Ok(());
+        }
+
+        match addr {
+            0xFF10 => self.channel1.write_sweep(val),
+            0xFF11 => self.channel1.write_length_duty(val),
+            0xFF12 => self.channel1.write_envelope(val),
+            0xFF13 => self.channel1.write_frequency_low(val),
+            0xFF14 => self.channel1.write_control(val),
+            0xFF16 => self.channel2.write_length_duty(val),
+            0xFF17 => self.channel2.write_envelope(val),
+            0xFF18 => self.channel2.write_frequency_low(val),
+            0xFF19 => self.channel2.write_control(val),
+            0xFF1A => self.channel3.write_dac(val),
+            0xFF1B => self.channel3.write_length(val),
+            0xFF1C => self.channel3.write_volume(val),
+            0xFF1D => self.channel3.write_frequency_low(val),
+            0xFF1E => self.channel3.write_control(val),
+            0xFF20 => self.channel4.write_length(val),
+            0xFF21 => self.channel4.write_envelope(val),
+            0xFF22 => self.channel4.write_polynomial(val),
+            0xFF23 => self.channel4.write_control(val),
+            0xFF24 => self.master_volume = val,
+            0xFF25 => self.panning = val,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn write_power(&mut self, val: u8) {
+        let power = val & 0x80 > 0;
+
+        if self.power && !power {
+            let ram = self.channel3.ram;
+
+            self.channel1 = SquareChannel::with_sweep();
+            self.channel2 = Default::default();
+            self.channel3 = WaveChannel {
+                ram,
+                ..Default::default()
+            };
+            self.channel4 = Default::default();
+            self.master_volume = 0;
+            self.panning = 0;
+        }
+
+        if !self.power && power {
+            self.frame_sequencer_cycles = 0;
+            self.frame_sequencer_step = 0;
+        }
+
+        self.power = power;
+    }
+}
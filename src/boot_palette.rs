@@ -0,0 +1,300 @@
+use crate::rom::Rom;
+
+// GBCのブートROMが持つ色の表 (RGB555)。4色ずつ30組のパレットが並ぶ
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, // 0
+    0x639F, 0x4279, 0x15B0, 0x04CB, // 1
+    0x7FFF, 0x6E31, 0x454A, 0x0000, // 2
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, // 3
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, // 4
+    0x7FFF, 0x5294, 0x294A, 0x0000, // 5
+    0x7FFF, 0x03FF, 0x012F, 0x0000, // 6
+    0x7FFF, 0x03EF, 0x01D6, 0x0000, // 7
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, // 8
+    0x7E74, 0x03FF, 0x0180, 0x0000, // 9
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, // 10
+    0x7ED6, 0x4BFF, 0x2175, 0x0000, // 11
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, // 12
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, // 13
+    0x03ED, 0x7FFF, 0x255F, 0x0000, // 14
+    0x036A, 0x021F, 0x03FF, 0x7FFF, // 15
+    0x7FFF, 0x01DF, 0x0112, 0x0000, // 16
+    0x231F, 0x035F, 0x00F2, 0x0009, // 17
+    0x7FFF, 0x03EA, 0x011F, 0x0000, // 18
+    0x299F, 0x001A, 0x000C, 0x0000, // 19
+    0x7FFF, 0x027F, 0x001F, 0x0000, // 20
+    0x7FFF, 0x03E0, 0x0206, 0x0120, // 21
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, // 22
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F, // 23
+    0x7FFF, 0x03FF, 0x001F, 0x0000, // 24
+    0x03FF, 0x001F, 0x000C, 0x0000, // 25
+    0x7FFF, 0x033F, 0x0193, 0x0000, // 26
+    0x0000, 0x4200, 0x037F, 0x7FFF, // 27
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, // 28
+    0x7FFF, 0x1BEF, 0x6180, 0x0000, // 29
+];
+
+// パレットの番号から、COLORSでの先頭の位置を求める
+const fn pal(index: u8) -> u8 {
+    index * 4
+}
+
+// OBJ0、OBJ1、BGそれぞれのCOLORSでの先頭の位置。
+// 殆どはパレットの先頭を指すが、いくつかはパレットの境界をまたいだ位置から4色を使う
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [pal(4), pal(4), pal(29)],         // 0, → + A (既定)
+    [pal(18), pal(18), pal(18)],       // 1, →
+    [pal(20), pal(20), pal(20)],       // 2
+    [pal(24), pal(24), pal(24)],       // 3, ↓ + A
+    [pal(9), pal(9), pal(9)],          // 4
+    [pal(0), pal(0), pal(0)],          // 5, ↑
+    [pal(27), pal(27), pal(27)],       // 6, → + B
+    [pal(5), pal(5), pal(5)],          // 7, ← + B
+    [pal(12), pal(12), pal(12)],       // 8, ↓
+    [pal(26), pal(26), pal(26)],       // 9
+    [pal(16), pal(8), pal(8)],         // 10
+    [pal(4), pal(28), pal(28)],        // 11
+    [pal(4), pal(2), pal(2)],          // 12
+    [pal(3), pal(4), pal(4)],          // 13
+    [pal(4), pal(29), pal(29)],        // 14
+    [pal(28), pal(4), pal(28)],        // 15
+    [pal(2), pal(17), pal(2)],         // 16
+    [pal(16), pal(16), pal(8)],        // 17
+    [pal(4), pal(4), pal(7)],          // 18
+    [pal(4), pal(4), pal(18)],         // 19
+    [pal(4), pal(4), pal(20)],         // 20
+    [pal(19), pal(19), pal(9)],        // 21
+    [pal(4) - 1, pal(4) - 1, pal(11)], // 22
+    [pal(17), pal(17), pal(2)],        // 23
+    [pal(4), pal(4), pal(2)],          // 24
+    [pal(4), pal(4), pal(3)],          // 25
+    [pal(28), pal(28), pal(0)],        // 26
+    [pal(3), pal(3), pal(0)],          // 27
+    [pal(0), pal(0), pal(1)],          // 28, ↑ + B
+    [pal(18), pal(22), pal(18)],       // 29
+    [pal(20), pal(22), pal(20)],       // 30
+    [pal(24), pal(22), pal(24)],       // 31
+    [pal(16), pal(22), pal(8)],        // 32
+    [pal(17), pal(4), pal(13)],        // 33
+    [pal(28) - 1, pal(0), pal(14)],    // 34
+    [pal(28) - 1, pal(4), pal(15)],    // 35
+    [pal(19), pal(22), pal(9)],        // 36
+    [pal(16), pal(28), pal(10)],       // 37
+    [pal(4), pal(23), pal(28)],        // 38
+    [pal(17), pal(22), pal(2)],        // 39
+    [pal(4), pal(0), pal(2)],          // 40, ← + A
+    [pal(4), pal(28), pal(3)],         // 41
+    [pal(28), pal(3), pal(0)],         // 42
+    [pal(3), pal(28), pal(4)],         // 43, ↑ + A
+    [pal(21), pal(28), pal(4)],        // 44
+    [pal(3), pal(28), pal(0)],         // 45
+    [pal(25), pal(3), pal(28)],        // 46
+    [pal(0), pal(28), pal(8)],         // 47
+    [pal(4), pal(3), pal(28)],         // 48, ←
+    [pal(28), pal(3), pal(6)],         // 49, ↓ + B
+    [pal(4), pal(28), pal(29)],        // 50
+];
+
+/// `palette`で選べる組み合わせの数
+pub const BOOT_PALETTE_COUNT: usize = COMBINATIONS.len();
+
+/// タイトルが表に無い場合やNintendo以外のソフトに使われる組み合わせ (→ + A)
+pub const DEFAULT_BOOT_PALETTE: usize = 0;
+
+/// 起動画面でのボタン操作と、それで選ばれる組み合わせの番号
+pub const BUTTON_PALETTES: [(&str, usize); 12] = [
+    ("up", 5),
+    ("up+a", 43),
+    ("up+b", 28),
+    ("left", 48),
+    ("left+a", 40),
+    ("left+b", 7),
+    ("down", 8),
+    ("down+a", 3),
+    ("down+b", 49),
+    ("right", 1),
+    ("right+a", 0),
+    ("right+b", 6),
+];
+
+// タイトル (0x0134-0x0143) のチェックサム
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, // 既定
+    0x88, // ALLEY WAY
+    0x16, // YAKUMAN
+    0x36, // BASEBALL
+    0xD1, // TENNIS
+    0xDB, // TETRIS
+    0xF2, // QIX
+    0x3C, // DR.MARIO
+    0x8C, // RADARMISSION
+    0x92, // F1RACE
+    0x3D, // YOSSY NO TAMAGO
+    0x5C, //
+    0x58, // X
+    0xC9, // MARIOLAND2
+    0x3E, // YOSSY NO COOKIE
+    0x70, // ZELDA
+    0x1D, //
+    0x59, //
+    0x69, // TETRIS FLASH
+    0x19, // DONKEY KONG
+    0x35, // MARIO'S PICROSS
+    0xA8, //
+    0x14, // POKEMON RED
+    0xAA, // POKEMON GREEN
+    0x75, // PICROSS 2
+    0x95, // YOSSY NO PANEPON
+    0x99, // KIRAKIRA KIDS
+    0x34, // GAMEBOY GALLERY
+    0x6F, // POCKETCAMERA
+    0x15, //
+    0xFF, // BALLOON KID
+    0x97, // KINGOFTHEZOO
+    0x4B, // DMG FOOTBALL
+    0x90, // WORLD CUP
+    0x17, // OTHELLO
+    0x10, // SUPER RC PRO-AM
+    0x39, // DYNABLASTER
+    0xF7, // BOY AND BLOB GB2
+    0xF6, // MEGAMAN
+    0xA2, // STAR WARS-NOA
+    0x49, //
+    0x4E, // WAVERACE
+    0x43, //
+    0x68, // LOLO2
+    0xE0, // YOSHI'S COOKIE
+    0x8B, // MYSTIC QUEST
+    0xF0, //
+    0xCE, // TOPRANKINGTENNIS
+    0x0C, // MANSELL
+    0x29, // MEGAMAN3
+    0xE8, // SPACE INVADERS
+    0xB7, // GAME&WATCH
+    0x86, // DONKEYKONGLAND95
+    0x9A, // ASTEROIDS/MISCMD
+    0x52, // STREET FIGHTER 2
+    0x01, // DEFENDER/JOUST
+    0x9D, // KILLERINSTINCT95
+    0x71, // TETRIS BLAST
+    0x9C, // PINOCCHIO
+    0xBD, //
+    0x5D, // BA.TOSHINDEN
+    0x6D, // NETTOU KOF 95
+    0x67, //
+    0x3F, // TETRIS PLUS
+    0x6B, // DONKEYKONGLAND 3
+    // ここからはチェックサムが衝突するため、4文字目も比較する
+    0xB3, //
+    0x46, // SUPER MARIOLAND
+    0x28, // GOLF
+    0xA5, // SOLARSTRIKER
+    0xC6, // GBWARS
+    0xD3, // KAERUNOTAMENI
+    0x27, //
+    0x61, // POKEMON BLUE
+    0x18, // DONKEYKONGLAND
+    0x66, // GAMEBOY GALLERY2
+    0x6A, // DONKEYKONGLAND 2
+    0xBF, // KID ICARUS
+    0x0D, // TETRIS2
+    0xF4, //
+    0xB3, // MOGURANYA
+    0x46, //
+    0x28, // GALAGA&GALAXIAN
+    0xA5, // BT2RAGNAROKWORLD
+    0xC6, // KEN GRIFFEY JR
+    0xD3, //
+    0x27, // MAGNETIC SOCCER
+    0x61, // VEGAS STAKES
+    0x18, //
+    0x66, // MILLI/CENTI/PEDE
+    0x6A, // MARIO & YOSHI
+    0xBF, // SOCCER
+    0x0D, // POKEBOM
+    0xF4, // G&W GALLERY
+    0xB3, // TETRIS ATTACK
+];
+
+// TITLE_CHECKSUMSの何番目から4文字目を比較するか
+const FIRST_DUPLICATE: usize = 65;
+
+// 衝突するチェックサムそれぞれのタイトルの4文字目
+const FOURTH_LETTERS: &[u8; TITLE_CHECKSUMS.len() - FIRST_DUPLICATE] =
+    b"BEFAARBEKEK R-URAR INAILICE R";
+
+// TITLE_CHECKSUMSと同じ並びの、COMBINATIONSの番号
+const TITLE_PALETTES: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+fn rgb555_to_rgba(color: u16) -> [u8; 4] {
+    let expand = |c: u16| {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+
+    [expand(color), expand(color >> 5), expand(color >> 10), 0xFF]
+}
+
+fn shades(offset: u8) -> [[u8; 4]; 4] {
+    let mut shades = [[0; 4]; 4];
+
+    for (i, shade) in shades.iter_mut().enumerate() {
+        *shade = rgb555_to_rgba(COLORS[offset as usize + i]);
+    }
+
+    shades
+}
+
+// GBCのブートROMがDMGのソフトに割り当てるBG/OBJ0/OBJ1のパレット (RGBA8)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BootPalette {
+    pub bg: [[u8; 4]; 4],
+    pub obj0: [[u8; 4]; 4],
+    pub obj1: [[u8; 4]; 4],
+}
+
+/// 組み合わせの番号からパレットを得る。範囲外の場合はNone
+pub fn palette(index: usize) -> Option<BootPalette> {
+    let &[obj0, obj1, bg] = COMBINATIONS.get(index)?;
+
+    Some(BootPalette {
+        bg: shades(bg),
+        obj0: shades(obj0),
+        obj1: shades(obj1),
+    })
+}
+
+/// タイトル (0x0134-0x0143) の16バイトの和
+pub fn title_checksum(title: &[u8]) -> u8 {
+    title.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// ブートROMはライセンシーがNintendoの場合のみタイトルを参照する
+fn is_nintendo(rom: &Rom) -> bool {
+    rom.old_licensee_code == 0x01
+        || (rom.old_licensee_code == 0x33 && &rom.new_licensee_code == b"01")
+}
+
+/// ブートROMと同じ規則でROMに割り当てられる組み合わせの番号を求める
+pub fn lookup(rom: &Rom) -> usize {
+    if !is_nintendo(rom) {
+        return DEFAULT_BOOT_PALETTE;
+    }
+
+    let checksum = title_checksum(&rom.title);
+    let fourth = rom.title[3];
+
+    TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .find(|&(i, &sum)| {
+            sum == checksum
+                && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == fourth)
+        })
+        .map_or(DEFAULT_BOOT_PALETTE, |(i, _)| TITLE_PALETTES[i] as usize)
+}
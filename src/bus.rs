@@ -1,10 +1,27 @@
+use crate::apu::{Apu, ApuState};
+use crate::error::{GbError, Result};
 use crate::joypad::Joypad;
-use crate::mbc::Mbc;
-use crate::ppu::Ppu;
-use crate::timer::Timer;
-use anyhow::Result;
+use crate::mbc::{Mbc, MbcState};
+use crate::ppu::{Ppu, PpuState};
+use crate::scheduler::{EventKind, Scheduler, SchedulerState};
+use crate::serial::{BufferSink, SerialSink};
+use crate::timer::{Timer, TimerState};
+use crate::utils::MemoryFill;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use bitfield::bitfield;
 use bitmatch::bitmatch;
+use serde::{Deserialize, Serialize};
+
+// 内部クロックで1バイト転送するのにかかるサイクル数 (8ビット x 512サイクル)
+const SERIAL_TRANSFER_CYCLES: u64 = 8 * 512;
+
+// OAM DMAは1Mサイクルに1バイトずつ0xA0バイトを転送する
+const OAM_DMA_LENGTH: u16 = 0xA0;
+const OAM_DMA_CYCLES: u64 = OAM_DMA_LENGTH as u64 * 4;
+
+/// 何も応答しない領域を読んだときの値。データバスがプルアップされているため0xFFになる
+pub const OPEN_BUS: u8 = 0xFF;
 
 bitfield! {
     #[derive(Default)]
@@ -17,19 +34,56 @@ bitfield! {
     pub joypad, set_joypad: 4;
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Model {
+    #[default]
+    Dmg,
+    Cgb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusState {
+    pub ram: Vec<u8>,
+    pub hram: Vec<u8>,
+    pub ie: u8,
+    pub sb: u8,
+    pub prev_serial: bool,
+    pub serial_clock: bool,
+    pub int_serial: bool,
+    pub dma: u8,
+    pub mbc: MbcState,
+    pub ppu: PpuState,
+    pub timer: TimerState,
+    pub apu: ApuState,
+    pub scheduler: SchedulerState,
+}
+
 pub struct Bus {
     pub ppu: Ppu,
     pub joypad: Joypad,
     pub timer: Timer,
-    // apu: Apu,
+    pub apu: Apu,
+    scheduler: Scheduler,
+    model: Model,
     ram: [u8; 0x8000],
     hram: [u8; 0x0080],
     mbc: Box<dyn Mbc + Send>,
 
     pub ie: Ie,
 
+    sb: u8,
     prev_serial: bool,
+    // SCのbit0 (内部クロック)
+    serial_clock: bool,
     int_serial: bool,
+    // 最後にDMAレジスタに書き込まれた値 (転送元の上位バイト)
+    dma: u8,
+    serial_buffer: BufferSink,
+    // 設定されている場合は、送信したバイトをserial_bufferの代わりにこちらに渡す
+    serial_sink: Option<Box<dyn SerialSink + Send>>,
+
+    // CPUのメモリアクセスで既に進めたサイクル数
+    access_cycles: u8,
 }
 
 impl Bus {
@@ -38,19 +92,181 @@ impl Bus {
             ram: [0; 0x8000],
             hram: [0; 0x0080],
             ie: Default::default(),
+            sb: 0,
             int_serial: false,
             prev_serial: false,
+            serial_clock: false,
+            dma: 0,
+            serial_buffer: Default::default(),
+            serial_sink: None,
+            access_cycles: 0,
             ppu,
             mbc,
             joypad: Default::default(),
             timer: Default::default(),
+            apu: Default::default(),
+            scheduler: Default::default(),
+            model: Default::default(),
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    // ジョイパッドの入力状態は含まない
+    pub fn snapshot(&self) -> BusState {
+        BusState {
+            ram: self.ram.to_vec(),
+            hram: self.hram.to_vec(),
+            ie: self.ie.0,
+            sb: self.sb,
+            prev_serial: self.prev_serial,
+            serial_clock: self.serial_clock,
+            int_serial: self.int_serial,
+            dma: self.dma,
+            mbc: self.mbc.snapshot(),
+            ppu: self.ppu.snapshot(),
+            timer: self.timer.snapshot(&self.scheduler),
+            apu: self.apu.snapshot(),
+            scheduler: self.scheduler.snapshot(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &BusState) -> Result<()> {
+        if state.ram.len() != self.ram.len() || state.hram.len() != self.hram.len() {
+            return Err(GbError::InvalidState("bus"));
+        }
+
+        self.mbc.restore(&state.mbc)?;
+        self.ppu.restore(&state.ppu)?;
+        self.apu.restore(&state.apu);
+        self.scheduler.restore(&state.scheduler);
+        self.timer.restore(&state.timer, &self.scheduler);
+
+        self.ram.copy_from_slice(&state.ram);
+        self.hram.copy_from_slice(&state.hram);
+        self.ie = Ie(state.ie);
+        self.sb = state.sb;
+        self.prev_serial = state.prev_serial;
+        self.serial_clock = state.serial_clock;
+        self.int_serial = state.int_serial;
+        self.dma = state.dma;
+        self.access_cycles = 0;
+
+        Ok(())
+    }
+
+    pub fn cartridge_ram(&self) -> &[u8] {
+        self.mbc.ram()
+    }
+
+    pub fn load_cartridge_ram(&mut self, data: &[u8]) {
+        self.mbc.load_ram(data);
+    }
+
+    pub fn fill_memory(&mut self, fill: MemoryFill) {
+        let mut bytes = fill.bytes();
+
+        for (dst, src) in self
+            .ram
+            .iter_mut()
+            .chain(self.hram.iter_mut())
+            .zip(&mut bytes)
+        {
+            *dst = src;
         }
+
+        self.ppu.fill_vram(&mut bytes);
     }
 
     pub fn tick(&mut self) -> Result<()> {
+        self.scheduler.advance();
+
+        // 予約されたイベントは、このサイクルの周辺機器の処理より先に反映する
+        while let Some(kind) = self.scheduler.pop_due() {
+            self.handle_event(kind);
+        }
+
         self.ppu.tick()?;
-        self.timer.tick();
-        // self.apu.tick()?;
+        self.apu.tick();
+        self.mbc.tick();
+
+        Ok(())
+    }
+
+    fn handle_event(&mut self, kind: EventKind) {
+        match kind {
+            EventKind::TimerReload => self.timer.reload(),
+            EventKind::TimerTick => self.timer.tick(&mut self.scheduler),
+            EventKind::SerialTransfer => {
+                // 通信相手がいないため、1が8ビット分送られてくる
+                self.sb = 0xFF;
+                self.prev_serial = false;
+                self.int_serial = true;
+            }
+            EventKind::OamDma => {}
+        }
+    }
+
+    // CPUのメモリアクセスは1回につき4サイクルかかるため、アクセスの前に周辺機器をその分進める
+    fn advance_access(&mut self) -> Result<()> {
+        for _ in 0..4 {
+            self.tick()?;
+        }
+
+        self.access_cycles = self.access_cycles.wrapping_add(4);
+
+        Ok(())
+    }
+
+    pub fn take_access_cycles(&mut self) -> u8 {
+        core::mem::take(&mut self.access_cycles)
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> Result<u8> {
+        self.advance_access()?;
+
+        if self.dma_blocks(addr) {
+            return Ok(OPEN_BUS);
+        }
+
+        if self.model == Model::Dmg && (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam_read();
+        }
+
+        self.read(addr)
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, val: u8) -> Result<()> {
+        self.advance_access()?;
+
+        if self.dma_blocks(addr) {
+            return Ok(());
+        }
+
+        self.write(addr, val)
+    }
+
+    // OAM DMA中はバスをDMAが使うため、CPUはI/OとHRAMにしかアクセスできない
+    fn dma_blocks(&self, addr: u16) -> bool {
+        addr < 0xFF00 && self.scheduler.is_scheduled(EventKind::OamDma)
+    }
+
+    pub fn cpu_read_word(&mut self, addr: u16) -> Result<u16> {
+        let low = self.cpu_read(addr)?;
+        let high = self.cpu_read(addr.wrapping_add(1))?;
+
+        Ok(((high as u16) << 8) | (low as u16))
+    }
+
+    pub fn cpu_write_word(&mut self, addr: u16, val: u16) -> Result<()> {
+        self.cpu_write(addr, (val & 0x00FF) as u8)?;
+        self.cpu_write(addr.wrapping_add(1), (val >> 8) as u8)?;
 
         Ok(())
     }
@@ -103,15 +319,17 @@ impl Bus {
             0xC000..=0xDFFF => Ok(self.ram[(addr - 0xC000) as usize]),
             0xE000..=0xFDFF => Ok(self.ram[(addr - 0xE000) as usize]),
             0xFE00..=0xFE9F => self.ppu.read_oam(addr),
-            0xFEA0..=0xFEFF => Ok(0),
+            0xFEA0..=0xFEFF => self.read_unusable(addr),
             0xFF00 => Ok(self.joypad.read()),
             0xFF01 => self.read_serial(),
             0xFF02 => self.read_serial_ctrl(),
-            0xFF04 => Ok(self.timer.read_div()),
+            0xFF04 => Ok(self.timer.read_div(&self.scheduler)),
             0xFF05 => Ok(self.timer.read_tima()),
             0xFF06 => Ok(self.timer.read_tma()),
             0xFF07 => Ok(self.timer.read_tac()),
             0xFF0F => self.read_irq(),
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF46 => Ok(self.dma),
             0xFF40 => self.ppu.read_lcd_control(),
             0xFF41 => self.ppu.read_lcd_status(),
             0xFF42 => self.ppu.read_scroll_y(),
@@ -123,15 +341,47 @@ impl Bus {
             0xFF49 => self.ppu.read_object_palette_1(),
             0xFF4A => self.ppu.read_window_y(),
             0xFF4B => self.ppu.read_window_x(),
+            0xFF6C if self.model == Model::Cgb => self.ppu.read_object_priority_mode(),
             0xFF80..=0xFFFE => Ok(self.hram[(addr - 0xFF80) as usize]),
             0xFFFF => Ok(self.ie.0),
-            _ => Ok(0),
+            // 存在しないI/Oレジスタ
+            _ => Ok(OPEN_BUS),
+        }
+    }
+
+    // INC rr/DEC rr のように、アドレスがメモリアクセス無しで16bitの加減算器を通る場合
+    pub fn idu_touch(&mut self, addr: u16) {
+        if self.model == Model::Dmg && (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam_write();
         }
     }
 
+    // 読み込みによるOAM bugはcpu_readで起こす
+    fn read_unusable(&self, addr: u16) -> Result<u8> {
+        match self.model {
+            Model::Dmg if self.ppu.oam_accessible() => Ok(0x00),
+            Model::Dmg => Ok(OPEN_BUS),
+            // CGB(rev E)では下位バイトの上位ニブルが2回並んだ値が返る
+            Model::Cgb => {
+                let high = (addr & 0x00F0) as u8;
+                Ok(high | (high >> 4))
+            }
+        }
+    }
+
+    pub fn io(&self) -> [u8; 0x80] {
+        let mut io = [0; 0x80];
+
+        for (i, val) in io.iter_mut().enumerate() {
+            *val = self.read(0xFF00 + i as u16).unwrap_or(0);
+        }
+
+        io
+    }
+
     pub fn read_word(&self, addr: u16) -> Result<u16> {
         let low = self.read(addr)?;
-        let high = self.read(addr + 1)?;
+        let high = self.read(addr.wrapping_add(1))?;
 
         Ok(((high as u16) << 8) | (low as u16))
     }
@@ -152,14 +402,30 @@ impl Bus {
         Ok(bitpack!("000jstlv"))
     }
 
+    pub fn set_serial_sink(&mut self, sink: Box<dyn SerialSink + Send>) {
+        self.serial_sink = Some(sink);
+    }
+
+    pub fn serial_output(&self) -> &str {
+        self.serial_buffer.output()
+    }
+
     pub fn read_serial(&self) -> Result<u8> {
-        // シリアル通信は一旦実装せず、デバッグ用途にだけ使う
-        Ok(0)
+        // 通信相手がいないため、転送中は1が送られてくる
+        if self.prev_serial {
+            return Ok(0xFF);
+        }
+
+        Ok(self.sb)
     }
 
+    #[bitmatch]
     pub fn read_serial_ctrl(&self) -> Result<u8> {
-        // シリアル通信は一旦実装せず、デバッグ用途にだけ使う
-        Ok(0)
+        // 転送が終わるとbit7が落ちる。未使用のビットは1が読める
+        let s = self.prev_serial as u8;
+        let i = self.serial_clock as u8;
+
+        Ok(bitpack!("s111111i"))
     }
 
     pub fn write(&mut self, addr: u16, val: u8) -> Result<()> {
@@ -184,11 +450,11 @@ impl Bus {
             0xFF01 => self.write_serial(val),
             0xFF02 => self.write_serial_ctrl(val),
             0xFF04 => {
-                self.timer.write_div(val);
+                self.timer.write_div(val, &mut self.scheduler);
                 Ok(())
             }
             0xFF05 => {
-                self.timer.write_tima(val);
+                self.timer.write_tima(val, &mut self.scheduler);
                 Ok(())
             }
             0xFF06 => {
@@ -196,10 +462,11 @@ impl Bus {
                 Ok(())
             }
             0xFF07 => {
-                self.timer.write_tac(val);
+                self.timer.write_tac(val, &mut self.scheduler);
                 Ok(())
             }
             0xFF0F => self.write_irq(val),
+            0xFF10..=0xFF3F => self.apu.write(addr, val),
             0xFF40 => self.ppu.write_lcd_control(val),
             0xFF41 => self.ppu.write_lcd_status(val),
             0xFF42 => self.ppu.write_scroll_y(val),
@@ -211,6 +478,7 @@ impl Bus {
             0xFF49 => self.ppu.write_object_palette_1(val),
             0xFF4A => self.ppu.write_window_y(val),
             0xFF4B => self.ppu.write_window_x(val),
+            0xFF6C if self.model == Model::Cgb => self.ppu.write_object_priority_mode(val),
             0xFF80..=0xFFFE => {
                 self.hram[(addr - 0xFF80) as usize] = val;
                 Ok(())
@@ -228,7 +496,7 @@ impl Bus {
         let high = (val >> 8) as u8;
 
         self.write(addr, low)?;
-        self.write(addr + 1, high)?;
+        self.write(addr.wrapping_add(1), high)?;
 
         Ok(())
     }
@@ -251,7 +519,9 @@ impl Bus {
     }
 
     pub fn write_serial(&mut self, val: u8) -> Result<()> {
-        eprintln!("SERIAL: {:#02X}", val);
+        log::debug!("SERIAL: {:#04X}", val);
+
+        self.sb = val;
 
         Ok(())
     }
@@ -261,24 +531,42 @@ impl Bus {
         #[bitmatch]
         let "s??????i" = val;
 
+        self.serial_clock = i > 0;
+
         if i > 0 {
-            eprintln!("SERIAL CTRL: INTERNAL CLOCK");
+            log::debug!("SERIAL CTRL: INTERNAL CLOCK");
         } else {
-            eprintln!("SERIAL CTRL: EXTERNAL CLOCK");
+            log::debug!("SERIAL CTRL: EXTERNAL CLOCK");
         }
 
         let cur = if s > 0 {
-            eprintln!("SERIAL CTRL: START TRANSFER");
+            log::debug!("SERIAL CTRL: START TRANSFER");
+
+            if !self.prev_serial {
+                match self.serial_sink.as_mut() {
+                    Some(sink) => sink.on_byte(self.sb),
+                    None => self.serial_buffer.on_byte(self.sb),
+                }
+
+                // 内部クロック (8192Hz) では8ビットの転送が終わると自動的に完了する
+                if i > 0 {
+                    self.scheduler
+                        .schedule(SERIAL_TRANSFER_CYCLES, EventKind::SerialTransfer);
+                }
+            }
 
             true
         } else {
-            eprintln!("SERIAL CTRL: NO TRANSFER");
+            log::debug!("SERIAL CTRL: NO TRANSFER");
 
             false
         };
 
         if self.prev_serial && !cur {
+            self.scheduler.cancel(EventKind::SerialTransfer);
             self.int_serial = true;
+            // 転送が終わると受信した1がSBに残る
+            self.sb = 0xFF;
         }
 
         self.prev_serial = cur;
@@ -286,13 +574,18 @@ impl Bus {
         Ok(())
     }
 
+    // 転送元は開始時にまとめて写し、転送にかかる間だけCPUのアクセスを塞ぐ
     pub fn write_dma(&mut self, val: u8) -> Result<()> {
         let base_addr = (val as u16) << 8;
 
-        for i in 0..0x100 {
-            self.write(0xFE00 + i, self.read(base_addr + i)?)?;
+        self.dma = val;
+
+        for i in 0..OAM_DMA_LENGTH {
+            self.write(0xFE00 + i, self.read(base_addr.wrapping_add(i))?)?;
         }
 
+        self.scheduler.schedule(OAM_DMA_CYCLES, EventKind::OamDma);
+
         Ok(())
     }
 }
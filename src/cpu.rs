@@ -1,8 +1,20 @@
 use crate::bus::Bus;
-use anyhow::{bail, Result};
+use crate::error::{GbError, Result};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use bitfield::bitfield;
 use bitmatch::bitmatch;
+#[cfg(feature = "std")]
 use rustyline::Editor;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+pub mod debugger;
+pub mod expr;
+
+use expr::{Condition, Expr};
 
 bitfield! {
     #[derive(Default)]
@@ -13,6 +25,29 @@ bitfield! {
     n, set_n: 6;
     z, set_z: 7;
 }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuState {
+    pub a: u8,
+    pub f: u8,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub stalls: u8,
+    pub ime: bool,
+    pub ime_pending: bool,
+    pub halt: bool,
+    pub halt_bug: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    // Noneの場合は常に止まる
+    pub condition: Option<Condition>,
+}
+
 pub struct Cpu {
     a: u8,
     f: F,
@@ -25,18 +60,32 @@ pub struct Cpu {
     stalls: u8,
 
     ime: bool,
+    ime_pending: bool,
     halt: bool,
+    halt_bug: bool,
 
     stepping: bool,
-    pub breakpoints: Vec<u16>,
+    // falseの場合はREPLに入らない (端末が無い環境でブロックしないように)。
+    // ライブラリとしては無効が既定で、REPLを使うフロントエンドが明示的に有効にする
+    pub debugger: bool,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watches: Vec<Expr>,
+    pub software_break: bool,
+    software_break_hit: bool,
+    #[cfg(feature = "std")]
     rl: Editor<()>,
+    #[cfg(feature = "std")]
     trace_left: u64,
 
+    instructions: u64,
+    // 0x000-0x0FF: 通常の命令, 0x100-0x1FF: CBプレフィックス付きの命令
+    opcode_histogram: Option<Box<[u64; 512]>>,
+
     pub bus: Bus,
 }
 
 impl Cpu {
-    pub fn new(bus: Bus, rl: Editor<()>) -> Self {
+    pub fn new(bus: Bus, #[cfg(feature = "std")] rl: Editor<()>) -> Self {
         Cpu {
             a: 0,
             f: Default::default(),
@@ -47,12 +96,22 @@ impl Cpu {
             pc: 0,
             stalls: 0,
             ime: false,
+            ime_pending: false,
             halt: false,
-            stepping: true,
+            halt_bug: false,
+            stepping: false,
+            debugger: false,
             breakpoints: Vec::new(),
+            watches: Vec::new(),
+            software_break: false,
+            software_break_hit: false,
+            #[cfg(feature = "std")]
             rl,
             // trace_left: 300000,
+            #[cfg(feature = "std")]
             trace_left: 0,
+            instructions: 0,
+            opcode_histogram: None,
             bus,
         }
     }
@@ -70,16 +129,75 @@ impl Cpu {
         Ok(())
     }
 
-    pub fn tick(&mut self) -> Result<()> {
+    /// レジスタに加えて、IME、EIの保留、HALT、残りのサイクル数等の外から見えない状態を返す。
+    /// バスの状態は含まない
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.a,
+            f: self.f.0,
+            bc: self.bc,
+            de: self.de,
+            hl: self.hl,
+            sp: self.sp,
+            pc: self.pc,
+            stalls: self.stalls,
+            ime: self.ime,
+            ime_pending: self.ime_pending,
+            halt: self.halt,
+            halt_bug: self.halt_bug,
+        }
+    }
+
+    /// セーブステートやテストの準備のために状態を直接設定する。Fの下位4ビットは常に0になる
+    pub fn set_state(&mut self, state: CpuState) {
+        self.a = state.a;
+        self.f = F(state.f & 0xF0);
+        self.bc = state.bc;
+        self.de = state.de;
+        self.hl = state.hl;
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.stalls = state.stalls;
+        self.ime = state.ime;
+        self.ime_pending = state.ime_pending;
+        self.halt = state.halt;
+        self.halt_bug = state.halt_bug;
+    }
+
+    /// 戻り値はメモリアクセスで既にバスを進めたサイクル数。
+    /// 0の場合は呼び出し側がバスを1サイクル進める。
+    pub fn tick(&mut self) -> Result<u8> {
         if self.stalls > 0 {
             self.stalls -= 1;
 
-            return Ok(());
+            return Ok(0);
         }
 
+        let result = self.execute();
+
+        // メモリアクセスの時点で既に周辺機器を進めた分は待たない
+        let accessed = self.bus.take_access_cycles();
+        self.stalls = self.stalls.saturating_sub(accessed);
+
+        // アクセスが無かった場合 (HALT中等) はこの呼び出し自体を1サイクルとして数える
+        if accessed == 0 {
+            self.stalls = self.stalls.saturating_sub(1);
+        }
+
+        result.map(|_| accessed)
+    }
+
+    fn execute(&mut self) -> Result<()> {
         self.stalls += 4;
 
         if self.ime {
+            // EI; HALT のようにHALTバグの直後に割り込みが入った場合は、
+            // HALT自体を戻り先にし、ハンドラの最初の命令ではPCを進める
+            if self.halt_bug && self.interrupt_pending()? {
+                self.pc = self.pc.wrapping_sub(1);
+                self.halt_bug = false;
+            }
+
             if let Some(mnemonic) = self.interrupt()? {
                 // println!("{}: IE={:?}", mnemonic, self.bus.ie);
 
@@ -89,48 +207,82 @@ impl Cpu {
         }
 
         if self.halt {
-            return Ok(());
+            // IMEに関わらず、割り込み要求があればHALTから復帰する
+            if !self.interrupt_pending()? {
+                return Ok(());
+            }
+
+            self.halt = false;
         }
 
-        let opecode = self.bus.read(self.pc)?;
+        // EIの効果は次の命令の実行後に反映される
+        let ime_pending = self.ime_pending;
+
+        let opecode = self.bus.cpu_read(self.pc)?;
 
-        let step = self.stepping || self.breakpoints.contains(&self.pc);
-        let trace = self.trace_left > 0;
+        #[cfg(feature = "std")]
+        let (step, trace) = self.debug_before(opecode);
 
-        if step {
-            println!(
-                "PC: {:#04X}, OPECODE: {:#02X}, A: {:#02X}, BC: {:#04X}, DE: {:#04X}, HL: {:#04X}, SP: {:#04X} FLAGS: {:?}, IE: {:?}, IRQ: {}",
-                self.pc, opecode, self.a, self.bc, self.de, self.hl, self.sp, self.f, self.bus.ie, self.bus.read_irq().map_or("ERR".to_string(), |v| format!("{:#02X}", v)),
-            );
+        // HALTバグ: HALT直後の1バイトはPCが進まず、2回読み込まれる
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
         }
 
-        if self.trace_left > 0 {
-            self.trace_left -= 1;
+        #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+        let mnemonic = self.do_mnemonic(opecode)?;
 
-            if self.trace_left == 0 {
-                self.debug_break();
-            }
+        if ime_pending && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
         }
 
-        if step {
-            self.debug_break();
+        // LD B, B をソフトウェアブレークポイントとして扱う (Mooneye等のテストROMで使われる)
+        if self.software_break && opecode == 0x40 {
+            self.software_break_hit = true;
+            // デバッガが無効の場合は実行を止めるだけで、REPLには入らない
+            self.stepping |= self.debugger;
         }
 
-        self.pc = self.pc.wrapping_add(1);
+        #[cfg(feature = "std")]
+        self.debug_after(opecode, &mnemonic, step, trace);
 
-        let mnemonic = self.do_mnemonic(opecode)?;
+        Ok(())
+    }
 
-        if step {
-            println!("{}", mnemonic);
+    fn count_opcode(&mut self, index: usize) {
+        if let Some(histogram) = self.opcode_histogram.as_mut() {
+            histogram[index] += 1;
         }
+    }
 
-        if trace {
-            println!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: {:04X} | {:04X}: {}",
-                self.a, self.f.0, self.b(), self.c(), self.d(), self.e(), self.h(), self.l(), self.sp, self.pc, opecode, mnemonic
-                );
-        }
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
 
-        Ok(())
+    pub fn set_opcode_histogram(&mut self, on: bool) {
+        self.opcode_histogram = if on { Some(Box::new([0; 512])) } else { None };
+    }
+
+    pub fn opcode_histogram(&self) -> Option<&[u64; 512]> {
+        self.opcode_histogram.as_deref()
+    }
+
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.stalls == 0
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn software_break_pending(&self) -> bool {
+        self.software_break_hit
+    }
+
+    pub fn take_software_break_hit(&mut self) -> bool {
+        core::mem::take(&mut self.software_break_hit)
     }
 
     pub fn b(&self) -> u8 {
@@ -193,6 +345,7 @@ impl Cpu {
 
     fn set_af(&mut self, val: u16) {
         self.a = (val >> 8) as u8;
+        // Fの下位4bitは常に0 (POP AFも含む)
         self.f.0 = (val & 0x00F0) as u8;
     }
 
@@ -204,9 +357,29 @@ impl Cpu {
             3 => Ok(self.e()),
             4 => Ok(self.h()),
             5 => Ok(self.l()),
-            6 => self.bus.read(self.hl),
+            6 => self.bus.cpu_read(self.hl),
             7 => Ok(self.a),
-            _ => bail!("unknown r8 {}", index),
+            _ => Err(GbError::UnknownRegister(index)),
+        }
+    }
+
+    // レジスタの場合はフェッチの4サイクルのみ、(HL)の場合は読み込みで更に4サイクルかかる。
+    // フェッチの4サイクルを除いた分を返す
+    fn r8_stalls(index: u8) -> u8 {
+        if index == 6 {
+            4
+        } else {
+            0
+        }
+    }
+
+    // CBプレフィックス付きの命令は2回のフェッチで8サイクル、(HL)の場合は読み書きで更に8サイクルかかる。
+    // 最初のフェッチの4サイクルを除いた分を返す
+    fn prefixed_r8_stalls(index: u8) -> u8 {
+        if index == 6 {
+            12
+        } else {
+            4
         }
     }
 
@@ -218,7 +391,7 @@ impl Cpu {
             3 => "E".to_string(),
             4 => "H".to_string(),
             5 => "L".to_string(),
-            6 => format!("{:#04X}", self.hl),
+            6 => format!("{:#06X}", self.hl),
             7 => "A".to_string(),
             _ => "?".to_string(),
         }
@@ -250,12 +423,12 @@ impl Cpu {
                 self.set_l(val);
                 Ok(())
             }
-            6 => self.bus.write(self.hl, val),
+            6 => self.bus.cpu_write(self.hl, val),
             7 => {
                 self.a = val;
                 Ok(())
             }
-            _ => bail!("unknown r8 {}", index),
+            _ => Err(GbError::UnknownRegister(index)),
         }
     }
 
@@ -266,7 +439,7 @@ impl Cpu {
             2 => Ok(self.hl),
             3 if high => Ok(self.af()),
             3 if !high => Ok(self.sp),
-            _ => bail!("unknown r16 {}", index),
+            _ => Err(GbError::UnknownRegister(index)),
         }
     }
 
@@ -303,7 +476,7 @@ impl Cpu {
                 self.sp = val;
                 Ok(())
             }
-            _ => bail!("unknown r16 {}", index),
+            _ => Err(GbError::UnknownRegister(index)),
         }
     }
 
@@ -327,14 +500,23 @@ impl Cpu {
         left.overflowing_add(right).1
     }
 
-    fn half_carry_positive_16(&self, left: u16, right: u16) -> bool {
-        (left & 0x00FF) + (right & 0x00FF) > 0x00FF
-    }
-
+    // ADD HL, rr のHはbit11からの桁上がり (SP+nはsp_add_flagsを使う)
     fn half_carry_positive_16_12(&self, left: u16, right: u16) -> bool {
         (left & 0x0FFF) + (right & 0x0FFF) > 0x0FFF
     }
 
+    // SP+nのH/Cはnの符号に関わらず下位バイトの符号なし加算から求める
+    fn sp_add_flags(&self, sp: u16, n: u8) -> (bool, bool) {
+        (
+            self.half_carry_positive(sp as u8, n),
+            self.carry_positive(sp as u8, n),
+        )
+    }
+
+    fn interrupt_pending(&self) -> Result<bool> {
+        Ok(self.bus.read_irq()? & self.bus.ie.0 & 0x1F != 0)
+    }
+
     fn interrupt(&mut self) -> Result<Option<String>> {
         let mut int = 0x0040;
 
@@ -391,6 +573,9 @@ impl Cpu {
 
     #[bitmatch]
     fn do_mnemonic(&mut self, opecode: u8) -> Result<String> {
+        self.instructions = self.instructions.wrapping_add(1);
+        self.count_opcode(opecode as usize);
+
         #[bitmatch]
         match &opecode {
             // NOP
@@ -520,7 +705,7 @@ impl Cpu {
             "11010010" => self.jp_16_nc(),
             // JP C, nn
             "11011010" => self.jp_16_c(),
-            // JP (HL)
+            // JP HL
             "11101001" => self.jp_16_hl(),
             // JR
             "00011000" => self.jr_8_im_8(),
@@ -558,20 +743,18 @@ impl Cpu {
             "11011001" => self.reti(),
             // CB Prefixed Instructions
             "11001011" => {
-                let prefixed = self.bus.read(self.pc)?;
+                let prefixed = self.bus.cpu_read(self.pc)?;
                 self.pc = self.pc.wrapping_add(1);
                 self.do_mnemonic_prefixed(prefixed)
             }
-            _ => {
-                eprintln!("unimplemented opecode {:#02X}", opecode);
-
-                Ok("UNIMPLEMENTED".to_string())
-            }
+            _ => Err(GbError::IllegalOpcode(opecode)),
         }
     }
 
     #[bitmatch]
     fn do_mnemonic_prefixed(&mut self, opecode: u8) -> Result<String> {
+        self.count_opcode(0x100 + opecode as usize);
+
         #[bitmatch]
         match &opecode {
             // SWAP r
@@ -597,7 +780,7 @@ impl Cpu {
             // RES b, r
             "10bbbxxx" => self.reset_8_bit_r(x, b),
             _ => {
-                eprintln!("unimplemented prefixed opecode {:#02X}", opecode);
+                log::warn!("unimplemented prefixed opecode {:#04X}", opecode);
 
                 Ok("UNIMPLEMENTED".to_string())
             }
@@ -609,6 +792,13 @@ impl Cpu {
     }
 
     pub fn halt(&mut self) -> Result<String> {
+        // IME=0で割り込みが既に要求されている場合はHALTせず、HALTバグが起きる
+        if !self.ime && self.interrupt_pending()? {
+            self.halt_bug = true;
+
+            return Ok("HALT (bug)".to_string());
+        }
+
         self.halt = true;
 
         Ok("HALT".to_string())
@@ -622,18 +812,19 @@ impl Cpu {
 
     pub fn di(&mut self) -> Result<String> {
         self.ime = false;
+        self.ime_pending = false;
 
         Ok("DI".to_string())
     }
 
     pub fn ei(&mut self) -> Result<String> {
-        self.ime = true;
+        self.ime_pending = true;
 
         Ok("EI".to_string())
     }
 
     pub fn load_8_r_im8(&mut self, index: u8) -> Result<String> {
-        let val = self.bus.read(self.pc)?;
+        let val = self.bus.cpu_read(self.pc)?;
 
         self.pc = self.pc.wrapping_add(1);
 
@@ -656,21 +847,21 @@ impl Cpu {
     }
 
     pub fn load_8_a_addr_bc(&mut self) -> Result<String> {
-        let val = self.bus.read(self.bc)?;
+        let val = self.bus.cpu_read(self.bc)?;
         self.a = val;
 
         Ok(format!("LD A, (BC): (BC)=({:04X})={:02X}", self.bc, val))
     }
 
     pub fn load_8_a_addr_de(&mut self) -> Result<String> {
-        let val = self.bus.read(self.de)?;
+        let val = self.bus.cpu_read(self.de)?;
         self.a = val;
 
-        Ok(format!("LD A, (DE): (DE)=({:04X})={:04X}", self.de, val))
+        Ok(format!("LD A, (DE): (DE)=({:04X})={:02X}", self.de, val))
     }
 
     pub fn load_8_addr_bc_a(&mut self) -> Result<String> {
-        self.bus.write(self.bc, self.a)?;
+        self.bus.cpu_write(self.bc, self.a)?;
 
         Ok(format!(
             "LD (BC), A: (BC)=({:04X}), A={:02X}",
@@ -679,7 +870,7 @@ impl Cpu {
     }
 
     pub fn load_8_addr_de_a(&mut self) -> Result<String> {
-        self.bus.write(self.de, self.a)?;
+        self.bus.cpu_write(self.de, self.a)?;
 
         Ok(format!(
             "LD (DE), A: (DE)=({:04X}), A={:02X}",
@@ -688,19 +879,19 @@ impl Cpu {
     }
 
     pub fn load_8_a_addr_im16(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
-        let val = self.bus.read(addr)?;
+        let val = self.bus.cpu_read(addr)?;
         self.a = val;
 
         Ok(format!("LD A, (nn): (nn)=({:04X})={:02X}", addr, val,))
     }
 
     pub fn load_8_addr_im16_a(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
         let val = self.a;
-        self.bus.write(addr, val)?;
+        self.bus.cpu_write(addr, val)?;
 
         Ok(format!("LD (nn), A: (nn)=({:04X}), A={:02X}", addr, val))
     }
@@ -708,7 +899,7 @@ impl Cpu {
     pub fn load_8_a_addr_index_c(&mut self) -> Result<String> {
         let index = self.c();
         let addr = 0xFF00 + index as u16;
-        let val = self.bus.read(addr)?;
+        let val = self.bus.cpu_read(addr)?;
         self.a = val;
 
         Ok(format!(
@@ -720,7 +911,7 @@ impl Cpu {
     pub fn load_8_addr_index_c_a(&mut self) -> Result<String> {
         let index = self.c();
         let addr = 0xFF00 + index as u16;
-        self.bus.write(addr, self.a)?;
+        self.bus.cpu_write(addr, self.a)?;
 
         Ok(format!(
             "LDH (C), A: (C)=({:02X})=({:04X})={:02X}",
@@ -729,10 +920,10 @@ impl Cpu {
     }
 
     pub fn load_8_a_addr_index_im8(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let addr = 0xFF00 + index as u16;
-        let val = self.bus.read(addr)?;
+        let val = self.bus.cpu_read(addr)?;
         self.a = val;
 
         Ok(format!(
@@ -742,10 +933,10 @@ impl Cpu {
     }
 
     pub fn load_8_addr_index_im8_a(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let addr = 0xFF00 + index as u16;
-        self.bus.write(addr, self.a)?;
+        self.bus.cpu_write(addr, self.a)?;
 
         Ok(format!(
             "LDH (n), A: (n)=({:02X})=({:04X}), A={:02X}",
@@ -754,7 +945,7 @@ impl Cpu {
     }
 
     pub fn load_dec_8_a_addr_hl(&mut self) -> Result<String> {
-        let val = self.bus.read(self.hl)?;
+        let val = self.bus.cpu_read(self.hl)?;
         self.hl = self.hl.wrapping_sub(1);
         self.a = val;
 
@@ -767,7 +958,7 @@ impl Cpu {
     }
 
     pub fn load_dec_8_addr_hl_a(&mut self) -> Result<String> {
-        self.bus.write(self.hl, self.a)?;
+        self.bus.cpu_write(self.hl, self.a)?;
         self.hl = self.hl.wrapping_sub(1);
 
         Ok(format!(
@@ -779,7 +970,7 @@ impl Cpu {
     }
 
     pub fn load_inc_8_a_addr_hl(&mut self) -> Result<String> {
-        let val = self.bus.read(self.hl)?;
+        let val = self.bus.cpu_read(self.hl)?;
         self.hl = self.hl.wrapping_add(1);
         self.a = val;
 
@@ -792,7 +983,7 @@ impl Cpu {
     }
 
     pub fn load_inc_8_addr_hl_a(&mut self) -> Result<String> {
-        self.bus.write(self.hl, self.a)?;
+        self.bus.cpu_write(self.hl, self.a)?;
         self.hl = self.hl.wrapping_add(1);
 
         Ok(format!(
@@ -804,7 +995,7 @@ impl Cpu {
     }
 
     pub fn load_16_rr_im16(&mut self, index: u8) -> Result<String> {
-        let val = self.bus.read_word(self.pc)?;
+        let val = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
         self.set_r16(index, val, false)?;
 
@@ -816,37 +1007,38 @@ impl Cpu {
     }
 
     pub fn load_16_addr_im16_sp(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
         let val = self.sp;
-        self.bus.write_word(addr, val)?;
+        self.bus.cpu_write_word(addr, val)?;
 
-        Ok(format!("LD (nn), sp: (nn)=({:04X}), SP={:04X}", addr, val))
+        Ok(format!("LD (nn), SP: (nn)=({:04X}), SP={:04X}", addr, val))
     }
 
     pub fn load_16_hl_index_im8_sp(&mut self) -> Result<String> {
         let base_addr = self.sp;
-        let index_addr = self.bus.read(self.pc)? as i8 as u16;
+        let index_addr = self.bus.cpu_read(self.pc)? as i8 as u16;
         self.pc = self.pc.wrapping_add(1);
         self.hl = base_addr.wrapping_add(index_addr);
 
+        let (h, c) = self.sp_add_flags(base_addr, index_addr as u8);
+
         self.f.set_z(false);
         self.f.set_n(false);
-        self.f
-            .set_h(self.half_carry_positive(base_addr as u8, index_addr as u8));
-        self.f
-            .set_c(self.carry_positive(base_addr as u8, index_addr as u8));
+        self.f.set_h(h);
+        self.f.set_c(c);
 
         Ok(format!(
             "LD HL, SP+n: SP={:04X}, n={:02X}, SP+n={:04X}",
-            self.sp, index_addr, self.hl
+            self.sp, index_addr as u8, self.hl
         ))
     }
 
     pub fn load_16_sp_hl(&mut self) -> Result<String> {
         self.sp = self.hl;
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 内部処理の4サイクル
+        self.stalls += 4;
 
         Ok(format!("LD SP, HL: HL={:04X}", self.hl))
     }
@@ -854,7 +1046,7 @@ impl Cpu {
     pub fn push_16_rr(&mut self, index: u8) -> Result<String> {
         let val = self.r16(index, true)?;
         self.sp = self.sp.wrapping_sub(2);
-        self.bus.write_word(self.sp, val)?;
+        self.bus.cpu_write_word(self.sp, val)?;
 
         self.stalls += 16;
 
@@ -866,7 +1058,7 @@ impl Cpu {
     }
 
     pub fn pop_16_rr(&mut self, index: u8) -> Result<String> {
-        let val = self.bus.read_word(self.sp)?;
+        let val = self.bus.cpu_read_word(self.sp)?;
         self.sp = self.sp.wrapping_add(2);
         self.set_r16(index, val, true)?;
 
@@ -902,7 +1094,7 @@ impl Cpu {
     }
 
     pub fn add_8_a_im8(&mut self) -> Result<String> {
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let left = self.a;
         let result = left.wrapping_add(right);
@@ -950,7 +1142,7 @@ impl Cpu {
 
     pub fn add_carry_8_a_im8(&mut self) -> Result<String> {
         let c = self.f.c() as u8;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let left = self.a;
         let result1 = left.wrapping_add(right);
@@ -997,7 +1189,7 @@ impl Cpu {
 
     pub fn sub_8_a_im8(&mut self) -> Result<String> {
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result = left.wrapping_sub(right);
 
@@ -1045,7 +1237,7 @@ impl Cpu {
     pub fn sub_carry_8_a_im8(&mut self) -> Result<String> {
         let c = self.f.c() as u8;
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result1 = left.wrapping_sub(right);
         let result2 = result1.wrapping_sub(c);
@@ -1079,7 +1271,7 @@ impl Cpu {
         self.f.set_h(true);
         self.f.set_c(false);
 
-        self.stalls += 4;
+        self.stalls += Self::r8_stalls(index);
 
         Ok(format!(
             "AND A, {}: A={:02X}, {0}={:02X}",
@@ -1091,7 +1283,7 @@ impl Cpu {
 
     pub fn and_8_a_im8(&mut self) -> Result<String> {
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result = left & right;
 
@@ -1102,7 +1294,8 @@ impl Cpu {
         self.f.set_h(true);
         self.f.set_c(false);
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 即値の読み込みの4サイクル
+        self.stalls += 4;
 
         Ok(format!("AND A, n: A={:02X}, n={:02X}", left, right))
     }
@@ -1119,7 +1312,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(false);
 
-        self.stalls += 4;
+        self.stalls += Self::r8_stalls(index);
 
         Ok(format!(
             "OR A, {}: A={:02X}, {0}={:02X}",
@@ -1131,7 +1324,7 @@ impl Cpu {
 
     pub fn or_8_a_im8(&mut self) -> Result<String> {
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result = left | right;
 
@@ -1142,7 +1335,8 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(false);
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 即値の読み込みの4サイクル
+        self.stalls += 4;
 
         Ok(format!("OR A, n: A={:02X}, n={:02X}", left, right))
     }
@@ -1159,7 +1353,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(false);
 
-        self.stalls += 4;
+        self.stalls += Self::r8_stalls(index);
 
         Ok(format!(
             "XOR A, {}: A={:02X}, {0}={:02X}",
@@ -1171,7 +1365,7 @@ impl Cpu {
 
     pub fn xor_8_a_im8(&mut self) -> Result<String> {
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result = left ^ right;
 
@@ -1182,7 +1376,8 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(false);
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 即値の読み込みの4サイクル
+        self.stalls += 4;
 
         Ok(format!("XOR A, n: A={:02X}, n={:02X}", left, right))
     }
@@ -1197,7 +1392,7 @@ impl Cpu {
         self.f.set_h(self.half_carry_negative(left, right));
         self.f.set_c(self.carry_negative(left, right));
 
-        self.stalls += 4;
+        self.stalls += Self::r8_stalls(index);
 
         Ok(format!(
             "CP A, {}: A={:02X}, {0}={:02X}",
@@ -1209,7 +1404,7 @@ impl Cpu {
 
     pub fn cp_8_a_im8(&mut self) -> Result<String> {
         let left = self.a;
-        let right = self.bus.read(self.pc)?;
+        let right = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         let result = left.wrapping_sub(right);
 
@@ -1218,7 +1413,8 @@ impl Cpu {
         self.f.set_h(self.half_carry_negative(left, right));
         self.f.set_c(self.carry_negative(left, right));
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 即値の読み込みの4サイクル
+        self.stalls += 4;
 
         Ok(format!("CP A, n: A={:02X}, n={:02X}", left, right))
     }
@@ -1262,6 +1458,7 @@ impl Cpu {
 
         self.hl = result;
 
+        // Zは変化しない
         self.f.set_n(false);
         self.f.set_h(self.half_carry_positive_16_12(left, right));
         self.f.set_c(self.carry_positive_16(left, right));
@@ -1278,21 +1475,22 @@ impl Cpu {
 
     pub fn add_16_sp_im8(&mut self) -> Result<String> {
         let left = self.sp;
-        let right = self.bus.read(self.pc)? as i8 as u16;
+        let right = self.bus.cpu_read(self.pc)? as i8 as u16;
         self.pc = self.pc.wrapping_add(1);
         let result = left.wrapping_add(right);
 
         self.sp = result;
 
+        let (h, c) = self.sp_add_flags(left, right as u8);
+
         self.f.set_z(false);
         self.f.set_n(false);
-        self.f
-            .set_h(self.half_carry_positive(left as u8, right as u8));
-        self.f.set_c(self.carry_positive(left as u8, right as u8));
+        self.f.set_h(h);
+        self.f.set_c(c);
 
         self.stalls += 16;
 
-        Ok(format!("ADD SP, n: SP={:04X}, n={:02X}", left, right))
+        Ok(format!("ADD SP, n: SP={:04X}, n={:02X}", left, right as u8))
     }
 
     pub fn inc_16_rr(&mut self, index: u8) -> Result<String> {
@@ -1301,8 +1499,10 @@ impl Cpu {
         let result = left.wrapping_add(right);
 
         self.set_r16(index, result, false)?;
+        self.bus.idu_touch(left);
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 内部処理の4サイクル
+        self.stalls += 4;
 
         Ok(format!(
             "INC {}: {0}={:04X}",
@@ -1317,8 +1517,10 @@ impl Cpu {
         let result = left.wrapping_sub(right);
 
         self.set_r16(index, result, false)?;
+        self.bus.idu_touch(left);
 
-        self.stalls += 8;
+        // フェッチの4サイクル + 内部処理の4サイクル
+        self.stalls += 4;
 
         Ok(format!(
             "DEC {}: {0}={:04X}",
@@ -1407,7 +1609,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(c == 1);
 
-        self.stalls += 8;
+        self.stalls += Self::prefixed_r8_stalls(index);
 
         Ok(format!(
             "RLC {}: {0}={:02X}, #={:02X}",
@@ -1429,7 +1631,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(c == 1);
 
-        self.stalls += 8;
+        self.stalls += Self::prefixed_r8_stalls(index);
 
         Ok(format!(
             "RL {}: {0}={:02X}, #={:02X}",
@@ -1451,7 +1653,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(c == 1);
 
-        self.stalls += 8;
+        self.stalls += Self::prefixed_r8_stalls(index);
 
         Ok(format!(
             "RRC {}: {0}={:02X}, #={:02X}",
@@ -1473,7 +1675,7 @@ impl Cpu {
         self.f.set_h(false);
         self.f.set_c(c == 1);
 
-        self.stalls += 8;
+        self.stalls += Self::prefixed_r8_stalls(index);
 
         Ok(format!(
             "RR {}: {0}={:02X}, #={:02X}",
@@ -1606,7 +1808,7 @@ impl Cpu {
     }
 
     pub fn jp_16(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = addr;
 
         self.stalls += 16;
@@ -1615,7 +1817,7 @@ impl Cpu {
     }
 
     pub fn jp_16_nz(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if !self.f.z() {
@@ -1628,7 +1830,7 @@ impl Cpu {
     }
 
     pub fn jp_16_z(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if self.f.z() {
@@ -1641,7 +1843,7 @@ impl Cpu {
     }
 
     pub fn jp_16_nc(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if !self.f.c() {
@@ -1654,7 +1856,7 @@ impl Cpu {
     }
 
     pub fn jp_16_c(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if self.f.c() {
@@ -1669,23 +1871,22 @@ impl Cpu {
     pub fn jp_16_hl(&mut self) -> Result<String> {
         self.pc = self.hl;
 
-        self.stalls += 4;
-
-        Ok(format!("JP (HL): (HL)=({:04X})", self.hl))
+        // フェッチの4サイクルのみ
+        Ok(format!("JP HL: HL={:04X}", self.hl))
     }
 
     pub fn jr_8_im_8(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
         self.pc = self.pc.wrapping_add(index as i8 as u16);
 
         self.stalls += 12;
 
-        Ok(format!("JR n: n={}", index))
+        Ok(format!("JR n: n={}", index as i8))
     }
 
     pub fn jr_8_nz(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
 
         if !self.f.z() {
@@ -1694,11 +1895,11 @@ impl Cpu {
 
         self.stalls += 12;
 
-        Ok(format!("JR NZ, n: NZ={}, n={}", !self.f.z(), index))
+        Ok(format!("JR NZ, n: NZ={}, n={}", !self.f.z(), index as i8))
     }
 
     pub fn jr_8_z(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
 
         if self.f.z() {
@@ -1707,11 +1908,11 @@ impl Cpu {
 
         self.stalls += 12;
 
-        Ok(format!("JR Z, n: Z={}, n={}", self.f.z(), index))
+        Ok(format!("JR Z, n: Z={}, n={}", self.f.z(), index as i8))
     }
 
     pub fn jr_8_nc(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
 
         if !self.f.c() {
@@ -1720,11 +1921,11 @@ impl Cpu {
 
         self.stalls += 12;
 
-        Ok(format!("JR NC, n: NC={}, n={}", !self.f.c(), index))
+        Ok(format!("JR NC, n: NC={}, n={}", !self.f.c(), index as i8))
     }
 
     pub fn jr_8_c(&mut self) -> Result<String> {
-        let index = self.bus.read(self.pc)?;
+        let index = self.bus.cpu_read(self.pc)?;
         self.pc = self.pc.wrapping_add(1);
 
         if self.f.c() {
@@ -1733,12 +1934,12 @@ impl Cpu {
 
         self.stalls += 12;
 
-        Ok(format!("JR C, n: C={}, n={}", self.f.c(), index))
+        Ok(format!("JR C, n: C={}, n={}", self.f.c(), index as i8))
     }
 
     pub fn call(&mut self, addr: u16) -> Result<()> {
         self.sp = self.sp.wrapping_sub(2);
-        self.bus.write_word(self.sp, self.pc)?;
+        self.bus.cpu_write_word(self.sp, self.pc)?;
         self.pc = addr;
 
         self.stalls += 24;
@@ -1747,7 +1948,7 @@ impl Cpu {
     }
 
     pub fn call_16(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         self.call(addr)?;
@@ -1756,7 +1957,7 @@ impl Cpu {
     }
 
     pub fn call_16_nz(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if !self.f.z() {
@@ -1767,7 +1968,7 @@ impl Cpu {
     }
 
     pub fn call_16_z(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if self.f.z() {
@@ -1778,7 +1979,7 @@ impl Cpu {
     }
 
     pub fn call_16_nc(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if !self.f.c() {
@@ -1789,7 +1990,7 @@ impl Cpu {
     }
 
     pub fn call_16_c(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.pc)?;
+        let addr = self.bus.cpu_read_word(self.pc)?;
         self.pc = self.pc.wrapping_add(2);
 
         if self.f.c() {
@@ -1802,7 +2003,7 @@ impl Cpu {
     pub fn restart(&mut self, param: u8) -> Result<String> {
         let addr = param as u16 * 0x08;
         self.sp = self.sp.wrapping_sub(2);
-        self.bus.write_word(self.sp, self.pc)?;
+        self.bus.cpu_write_word(self.sp, self.pc)?;
         self.pc = addr;
 
         self.stalls += 16;
@@ -1811,7 +2012,7 @@ impl Cpu {
     }
 
     pub fn ret(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let addr = self.bus.cpu_read_word(self.sp)?;
         self.sp = self.sp.wrapping_add(2);
         self.pc = addr;
 
@@ -1825,7 +2026,8 @@ impl Cpu {
     }
 
     pub fn ret_nz(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let sp = self.sp;
+        let addr = self.bus.cpu_read_word(sp)?;
 
         if !self.f.z() {
             self.sp = self.sp.wrapping_add(2);
@@ -1837,13 +2039,14 @@ impl Cpu {
         Ok(format!(
             "RET NZ: NZ={}, (SP)=({:04X})={:04X}",
             !self.f.z(),
-            self.sp.wrapping_sub(2),
+            sp,
             addr
         ))
     }
 
     pub fn ret_z(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let sp = self.sp;
+        let addr = self.bus.cpu_read_word(sp)?;
 
         if self.f.z() {
             self.sp = self.sp.wrapping_add(2);
@@ -1855,13 +2058,14 @@ impl Cpu {
         Ok(format!(
             "RET Z: Z={}, (SP)=({:04X})={:04X}",
             self.f.z(),
-            self.sp.wrapping_sub(2),
+            sp,
             addr
         ))
     }
 
     pub fn ret_nc(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let sp = self.sp;
+        let addr = self.bus.cpu_read_word(sp)?;
 
         if !self.f.c() {
             self.sp = self.sp.wrapping_add(2);
@@ -1873,13 +2077,14 @@ impl Cpu {
         Ok(format!(
             "RET NC: NC={}, (SP)=({:04X})={:04X}",
             !self.f.c(),
-            self.sp.wrapping_sub(2),
+            sp,
             addr
         ))
     }
 
     pub fn ret_c(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let sp = self.sp;
+        let addr = self.bus.cpu_read_word(sp)?;
 
         if self.f.c() {
             self.sp = self.sp.wrapping_add(2);
@@ -1891,17 +2096,19 @@ impl Cpu {
         Ok(format!(
             "RET C: C={}, (SP)=({:04X})={:04X}",
             self.f.c(),
-            self.sp.wrapping_sub(2),
+            sp,
             addr
         ))
     }
 
     pub fn reti(&mut self) -> Result<String> {
-        let addr = self.bus.read_word(self.sp)?;
+        let addr = self.bus.cpu_read_word(self.sp)?;
         self.sp = self.sp.wrapping_add(2);
         self.pc = addr;
 
+        // RETIはEIと異なり即座に割り込みを有効にする
         self.ime = true;
+        self.ime_pending = false;
 
         self.stalls += 16;
 
@@ -1999,92 +2206,4 @@ impl Cpu {
 
         Ok("SCF".to_string())
     }
-
-    pub fn debug_break(&mut self) {
-        loop {
-            let readline = self.rl.readline(">>> ");
-
-            match readline {
-                Ok(line) if line.starts_with("continue") || line == "c" => {
-                    self.rl.add_history_entry(line.as_str());
-                    self.stepping = false;
-                    break;
-                }
-                Ok(line) if line.starts_with("step") || line == "s" => {
-                    self.rl.add_history_entry(line.as_str());
-                    self.stepping = true;
-                    break;
-                }
-                Ok(line) if line.starts_with("break ") || line.starts_with("b ") => {
-                    if let Some(addr_str) = line.split_ascii_whitespace().nth(1) {
-                        if let Ok(addr) = u16::from_str_radix(addr_str.trim_start_matches("0x"), 16)
-                        {
-                            self.rl.add_history_entry(line.as_str());
-                            self.breakpoints.push(addr);
-
-                            println!("add breakpoint: {:#04X}", addr);
-                            continue;
-                        }
-                    }
-
-                    println!("break command parse failed");
-                }
-                Ok(line) if line.starts_with("print ") || line.starts_with("p ") => {
-                    if let Some(addr_str) = line.split_ascii_whitespace().nth(1) {
-                        if let Ok(addr) = u16::from_str_radix(addr_str.trim_start_matches("0x"), 16)
-                        {
-                            if let Ok(val) = self.bus.read(addr) {
-                                self.rl.add_history_entry(line.as_str());
-                                println!("({:#04X})={:#02X}", addr, val);
-                                continue;
-                            }
-                        }
-                    }
-
-                    println!("print command failed");
-                }
-                Ok(line) if line.starts_with("printw ") || line.starts_with("pw ") => {
-                    if let Some(addr_str) = line.split_ascii_whitespace().nth(1) {
-                        if let Ok(addr) = u16::from_str_radix(addr_str.trim_start_matches("0x"), 16)
-                        {
-                            if let Ok(val) = self.bus.read_word(addr) {
-                                self.rl.add_history_entry(line.as_str());
-                                println!("({:#04X})={:#04X}", addr, val);
-                                continue;
-                            }
-                        }
-                    }
-
-                    println!("printw command parse failed");
-                }
-                Ok(line) if line.starts_with("reset") || line == "r" => {
-                    self.rl.add_history_entry(line.as_str());
-                    if let Err(err) = self.reset() {
-                        println!("failed to reset {}", err);
-                    }
-
-                    break;
-                }
-                Ok(line) if line.starts_with("trace ") || line.starts_with("t ") => {
-                    self.rl.add_history_entry(line.as_str());
-                    if let Some(num_str) = line.split_ascii_whitespace().nth(1) {
-                        if let Ok(num) = num_str.parse() {
-                            self.trace_left = num;
-                            self.stepping = false;
-                            break;
-                        }
-                    }
-
-                    println!("print command failed");
-                }
-                Ok(line) => {
-                    println!("unknown command {}", line);
-                }
-                Err(_) => {
-                    println!("aborted");
-                    std::process::exit(0);
-                }
-            }
-        }
-    }
 }
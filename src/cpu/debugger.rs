@@ -0,0 +1,247 @@
+use super::expr::{Condition, Expr};
+use super::{Breakpoint, Cpu};
+
+/// `set <addr> <val>` の引数を解釈する。どちらも16進数で、`0x`は省略できる
+pub fn parse_set(args: &str) -> Option<(u16, u8)> {
+    let mut args = args.split_ascii_whitespace();
+
+    let addr = u16::from_str_radix(args.next()?.trim_start_matches("0x"), 16).ok()?;
+    let val = u8::from_str_radix(args.next()?.trim_start_matches("0x"), 16).ok()?;
+
+    if args.next().is_some() {
+        return None;
+    }
+
+    Some((addr, val))
+}
+
+/// `break <addr> [if <条件>]` の引数を解釈する。アドレスは16進数で、`$`か`0x`は省略できる
+pub fn parse_break(args: &str) -> Option<Breakpoint> {
+    let (addr, condition) = match args.find(" if ") {
+        Some(i) => (
+            &args[..i],
+            Some(Condition::parse(&args[i + " if ".len()..])?),
+        ),
+        None => (args, None),
+    };
+    let addr = addr.trim();
+    let addr = addr
+        .strip_prefix('$')
+        .or_else(|| addr.strip_prefix("0x"))
+        .unwrap_or(addr);
+
+    Some(Breakpoint {
+        addr: u16::from_str_radix(addr, 16).ok()?,
+        condition,
+    })
+}
+
+impl Cpu {
+    // 命令の実行前に呼ばれる。ステップ実行中かどうかとトレース中かどうかを返す
+    pub(super) fn debug_before(&mut self, opecode: u8) -> (bool, bool) {
+        let step = self.debugger && (self.stepping || self.hit_breakpoint());
+        let trace = self.trace_left > 0;
+
+        if step {
+            println!(
+                "PC: {:#06X}, OPECODE: {:#04X}, A: {:#04X}, BC: {:#06X}, DE: {:#06X}, HL: {:#06X}, SP: {:#06X} FLAGS: {:?}, IE: {:?}, IRQ: {}",
+                self.pc, opecode, self.a, self.bc, self.de, self.hl, self.sp, self.f, self.bus.ie, self.bus.read_irq().map_or("ERR".to_string(), |v| format!("{:#04X}", v)),
+            );
+        }
+
+        if self.trace_left > 0 {
+            self.trace_left -= 1;
+
+            if self.trace_left == 0 {
+                self.debug_break();
+            }
+        }
+
+        if step {
+            self.debug_break();
+        }
+
+        (step, trace)
+    }
+
+    pub(super) fn debug_after(&self, opecode: u8, mnemonic: &str, step: bool, trace: bool) {
+        if step {
+            println!("{}", mnemonic);
+        }
+
+        if trace {
+            println!("{}", self.trace_line(opecode, mnemonic));
+        }
+    }
+
+    /// トレースの1行。8bitのレジスタは2桁、SPとPCは4桁の16進数
+    pub fn trace_line(&self, opecode: u8, mnemonic: &str) -> String {
+        format!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: {:04X} | {:02X}: {}",
+            self.a, self.f.0, self.b(), self.c(), self.d(), self.e(), self.h(), self.l(), self.sp, self.pc, opecode, mnemonic
+        )
+    }
+
+    fn hit_breakpoint(&self) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|b| b.addr == self.pc && b.condition.is_none_or(|cond| self.eval_condition(&cond)))
+    }
+
+    fn format_watch(&self, expr: &Expr) -> Option<String> {
+        let val = self.eval(expr)?;
+
+        if expr.is_word() {
+            Some(format!("{:#06X}", val))
+        } else {
+            Some(format!("{:#04X}", val))
+        }
+    }
+
+    fn print_watches(&self) {
+        for (i, expr) in self.watches.iter().enumerate() {
+            match self.format_watch(expr) {
+                Some(val) => println!("{}: {} = {}", i, expr, val),
+                None => println!("{}: {} = <error>", i, expr),
+            }
+        }
+    }
+
+    pub fn debug_break(&mut self) {
+        if !self.debugger {
+            return;
+        }
+
+        self.print_watches();
+
+        loop {
+            let readline = self.rl.readline(">>> ");
+
+            match readline {
+                Ok(line) if line.starts_with("continue") || line == "c" => {
+                    self.rl.add_history_entry(line.as_str());
+                    self.stepping = false;
+                    break;
+                }
+                Ok(line) if line.starts_with("step") || line == "s" => {
+                    self.rl.add_history_entry(line.as_str());
+                    self.stepping = true;
+                    break;
+                }
+                Ok(line) if line.starts_with("break ") || line.starts_with("b ") => {
+                    let args = line.split_once(' ').map_or("", |(_, args)| args);
+
+                    if let Some(breakpoint) = parse_break(args) {
+                        self.rl.add_history_entry(line.as_str());
+                        self.breakpoints.push(breakpoint);
+
+                        println!("add breakpoint: {:#06X}", breakpoint.addr);
+                        continue;
+                    }
+
+                    println!("break command parse failed");
+                }
+                Ok(line) if line.starts_with("print ") || line.starts_with("p ") => {
+                    if let Some(addr_str) = line.split_ascii_whitespace().nth(1) {
+                        if let Ok(addr) = u16::from_str_radix(addr_str.trim_start_matches("0x"), 16)
+                        {
+                            if let Ok(val) = self.bus.read(addr) {
+                                self.rl.add_history_entry(line.as_str());
+                                println!("({:#06X})={:#04X}", addr, val);
+                                continue;
+                            }
+                        }
+                    }
+
+                    println!("print command failed");
+                }
+                Ok(line) if line.starts_with("printw ") || line.starts_with("pw ") => {
+                    if let Some(addr_str) = line.split_ascii_whitespace().nth(1) {
+                        if let Ok(addr) = u16::from_str_radix(addr_str.trim_start_matches("0x"), 16)
+                        {
+                            if let Ok(val) = self.bus.read_word(addr) {
+                                self.rl.add_history_entry(line.as_str());
+                                println!("({:#06X})={:#06X}", addr, val);
+                                continue;
+                            }
+                        }
+                    }
+
+                    println!("printw command parse failed");
+                }
+                Ok(line) if line.starts_with("display ") => {
+                    if let Some(expr) = Expr::parse(&line["display ".len()..]) {
+                        if let Some(val) = self.format_watch(&expr) {
+                            self.rl.add_history_entry(line.as_str());
+                            println!("{}: {} = {}", self.watches.len(), expr, val);
+                            self.watches.push(expr);
+                            continue;
+                        }
+                    }
+
+                    println!("display command parse failed");
+                }
+                Ok(line) if line == "display" => {
+                    self.rl.add_history_entry(line.as_str());
+                    self.print_watches();
+                }
+                Ok(line) if line.starts_with("undisplay ") => {
+                    if let Some(Ok(index)) = line.split_ascii_whitespace().nth(1).map(str::parse) {
+                        if index < self.watches.len() {
+                            self.rl.add_history_entry(line.as_str());
+                            self.watches.remove(index);
+                            continue;
+                        }
+                    }
+
+                    println!("undisplay command parse failed");
+                }
+                Ok(line) if line.starts_with("set ") => {
+                    if let Some((addr, val)) = parse_set(&line["set ".len()..]) {
+                        self.rl.add_history_entry(line.as_str());
+
+                        // LYは読み込み専用。
+                        // 0x0000-0x7FFFはROMではなくMBCのレジスタへの書き込みになる
+                        if addr == 0xFF44 {
+                            println!("read-only address: {:#06X}", addr);
+                        } else if let Err(err) = self.bus.write(addr, val) {
+                            println!("failed to set {}", err);
+                        } else {
+                            println!("({:#06X})={:#04X}", addr, val);
+                        }
+
+                        continue;
+                    }
+
+                    println!("set command parse failed");
+                }
+                Ok(line) if line.starts_with("reset") || line == "r" => {
+                    self.rl.add_history_entry(line.as_str());
+                    if let Err(err) = self.reset() {
+                        println!("failed to reset {}", err);
+                    }
+
+                    break;
+                }
+                Ok(line) if line.starts_with("trace ") || line.starts_with("t ") => {
+                    self.rl.add_history_entry(line.as_str());
+                    if let Some(num_str) = line.split_ascii_whitespace().nth(1) {
+                        if let Ok(num) = num_str.parse() {
+                            self.trace_left = num;
+                            self.stepping = false;
+                            break;
+                        }
+                    }
+
+                    println!("print command failed");
+                }
+                Ok(line) => {
+                    println!("unknown command {}", line);
+                }
+                Err(_) => {
+                    println!("aborted");
+                    std::process::exit(0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use super::Cpu;
+use core::fmt;
+
+/// デバッガの式で参照できるレジスタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+const REGISTERS: [(&str, Register); 14] = [
+    ("A", Register::A),
+    ("F", Register::F),
+    ("B", Register::B),
+    ("C", Register::C),
+    ("D", Register::D),
+    ("E", Register::E),
+    ("H", Register::H),
+    ("L", Register::L),
+    ("AF", Register::AF),
+    ("BC", Register::BC),
+    ("DE", Register::DE),
+    ("HL", Register::HL),
+    ("SP", Register::SP),
+    ("PC", Register::PC),
+];
+
+impl Register {
+    fn parse(s: &str) -> Option<Self> {
+        REGISTERS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, reg)| reg)
+    }
+
+    fn name(self) -> &'static str {
+        REGISTERS
+            .iter()
+            .find(|&&(_, reg)| reg == self)
+            .map_or("?", |&(name, _)| name)
+    }
+
+    fn is_word(self) -> bool {
+        matches!(
+            self,
+            Register::AF | Register::BC | Register::DE | Register::HL | Register::SP | Register::PC
+        )
+    }
+}
+
+// `$FF44`、`0xFF44`の形式の16進数。接頭辞の無い値はレジスタ名と区別できないため受け付けない
+fn parse_hex(s: &str) -> Option<u16> {
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .or_else(|| s.strip_prefix("0X"))?;
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// ウォッチと条件付きブレークポイントで共通の式
+/// - `A`、`HL`等: レジスタの値 (`C`は常にレジスタC)
+/// - `$FF44`、`0xFF44`、`($FF44)`: そのアドレスのメモリ
+/// - `(HL)`等: 16bitレジスタの値をアドレスとするメモリ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    Register(Register),
+    Memory(u16),
+    Indirect(Register),
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+
+        if let Some(inner) = s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+            let inner = inner.trim();
+
+            return match Register::parse(inner) {
+                Some(reg) if reg.is_word() => Some(Expr::Indirect(reg)),
+                Some(_) => None,
+                None => parse_hex(inner).map(Expr::Memory),
+            };
+        }
+
+        Register::parse(s)
+            .map(Expr::Register)
+            .or_else(|| parse_hex(s).map(Expr::Memory))
+    }
+
+    /// 16bitの値になるかどうか (表示の桁数に使う)
+    pub fn is_word(&self) -> bool {
+        matches!(self, Expr::Register(reg) if reg.is_word())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Register(reg) => write!(f, "{}", reg.name()),
+            Expr::Memory(addr) => write!(f, "(${:04X})", addr),
+            Expr::Indirect(reg) => write!(f, "({})", reg.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+// 前方一致で誤判定しないよう、2文字の演算子を先に探す
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+/// `<式> <比較演算子> <値>`の形式の条件。値は`$`か`0x`を付けると16進数、付けなければ10進数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Expr,
+    pub cmp: Comparison,
+    pub rhs: u16,
+}
+
+impl Condition {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (op, cmp) = COMPARISONS.iter().find(|(op, _)| s.contains(op))?;
+        let (lhs, rhs) = s.split_at(s.find(op)?);
+        let rhs = rhs[op.len()..].trim();
+
+        Some(Condition {
+            lhs: Expr::parse(lhs)?,
+            cmp: *cmp,
+            rhs: parse_hex(rhs).or_else(|| rhs.parse().ok())?,
+        })
+    }
+}
+
+impl Cpu {
+    fn register(&self, reg: Register) -> u16 {
+        match reg {
+            Register::A => self.a as u16,
+            Register::F => self.f.0 as u16,
+            Register::B => self.b() as u16,
+            Register::C => self.c() as u16,
+            Register::D => self.d() as u16,
+            Register::E => self.e() as u16,
+            Register::H => self.h() as u16,
+            Register::L => self.l() as u16,
+            Register::AF => self.af(),
+            Register::BC => self.bc,
+            Register::DE => self.de,
+            Register::HL => self.hl,
+            Register::SP => self.sp,
+            Register::PC => self.pc,
+        }
+    }
+
+    /// 式を評価する。メモリが読めない場合はNone
+    pub fn eval(&self, expr: &Expr) -> Option<u16> {
+        match *expr {
+            Expr::Register(reg) => Some(self.register(reg)),
+            Expr::Memory(addr) => self.bus.read(addr).ok().map(u16::from),
+            Expr::Indirect(reg) => self.bus.read(self.register(reg)).ok().map(u16::from),
+        }
+    }
+
+    /// 条件を評価する。左辺を評価できない場合は偽
+    pub fn eval_condition(&self, cond: &Condition) -> bool {
+        let lhs = match self.eval(&cond.lhs) {
+            Some(lhs) => lhs,
+            None => return false,
+        };
+
+        match cond.cmp {
+            Comparison::Eq => lhs == cond.rhs,
+            Comparison::Ne => lhs != cond.rhs,
+            Comparison::Le => lhs <= cond.rhs,
+            Comparison::Ge => lhs >= cond.rhs,
+            Comparison::Lt => lhs < cond.rhs,
+            Comparison::Gt => lhs > cond.rhs,
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use bitmatch::bitmatch;
+use core::fmt::Write;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB", "SBC A,", "AND", "XOR", "OR", "CP",
+];
+const PREFIXED: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// リセット直後の実行開始位置と、RST/割り込みのベクタ
+const ENTRY_POINTS: [u16; 14] = [
+    0x0100, 0x0000, 0x0008, 0x0010, 0x0018, 0x0020, 0x0028, 0x0030, 0x0038, 0x0040, 0x0048, 0x0050,
+    0x0058, 0x0060,
+];
+
+// カートリッジのバンク切り替え無しで見えるROMの範囲
+const ROM_END: usize = 0x8000;
+
+// 命令の実行後にどこへ進むか
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump(u16),
+    // 条件付きのジャンプ。条件を満たさない場合は次の命令に進む
+    Branch(u16),
+    Call(u16),
+    Return,
+    ConditionalReturn,
+    // JP HL やSTOP等、静的には行き先がわからない
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub len: u8,
+    pub text: String,
+    pub flow: Flow,
+}
+
+// ROMの範囲外や未定義の命令の場合はNone
+#[bitmatch]
+pub fn decode(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let byte = |offset: u16| rom.get(addr.wrapping_add(offset) as usize).copied();
+    let opecode = byte(0)?;
+
+    let n = || byte(1);
+    let nn = || Some(u16::from_le_bytes([byte(1)?, byte(2)?]));
+    let relative = |n: u8| addr.wrapping_add(2).wrapping_add(n as i8 as u16);
+
+    let (len, text, flow) = #[bitmatch]
+    match opecode {
+        "00000000" => (1, "NOP".to_string(), Flow::Next),
+        "01110110" => (1, "HALT".to_string(), Flow::Next),
+        "00010000" => (2, "STOP".to_string(), Flow::Stop),
+        "11110011" => (1, "DI".to_string(), Flow::Next),
+        "11111011" => (1, "EI".to_string(), Flow::Next),
+        "01xxxyyy" => (
+            1,
+            format!("LD {}, {}", R8[x as usize], R8[y as usize]),
+            Flow::Next,
+        ),
+        "00xxx110" => (
+            2,
+            format!("LD {}, ${:02X}", R8[x as usize], n()?),
+            Flow::Next,
+        ),
+        "00001010" => (1, "LD A, (BC)".to_string(), Flow::Next),
+        "00011010" => (1, "LD A, (DE)".to_string(), Flow::Next),
+        "00000010" => (1, "LD (BC), A".to_string(), Flow::Next),
+        "00010010" => (1, "LD (DE), A".to_string(), Flow::Next),
+        "11111010" => (3, format!("LD A, (${:04X})", nn()?), Flow::Next),
+        "11101010" => (3, format!("LD (${:04X}), A", nn()?), Flow::Next),
+        "11110010" => (1, "LDH A, (C)".to_string(), Flow::Next),
+        "11100010" => (1, "LDH (C), A".to_string(), Flow::Next),
+        "11110000" => (2, format!("LDH A, ($FF{:02X})", n()?), Flow::Next),
+        "11100000" => (2, format!("LDH ($FF{:02X}), A", n()?), Flow::Next),
+        "00111010" => (1, "LD A, (HL-)".to_string(), Flow::Next),
+        "00110010" => (1, "LD (HL-), A".to_string(), Flow::Next),
+        "00101010" => (1, "LD A, (HL+)".to_string(), Flow::Next),
+        "00100010" => (1, "LD (HL+), A".to_string(), Flow::Next),
+        "00xx0001" => (
+            3,
+            format!("LD {}, ${:04X}", R16[x as usize], nn()?),
+            Flow::Next,
+        ),
+        "00001000" => (3, format!("LD (${:04X}), SP", nn()?), Flow::Next),
+        "11111000" => (2, format!("LD HL, SP{:+}", n()? as i8), Flow::Next),
+        "11111001" => (1, "LD SP, HL".to_string(), Flow::Next),
+        "11xx0101" => (1, format!("PUSH {}", R16_STACK[x as usize]), Flow::Next),
+        "11xx0001" => (1, format!("POP {}", R16_STACK[x as usize]), Flow::Next),
+        "10xxxyyy" => (
+            1,
+            format!("{} {}", ALU[x as usize], R8[y as usize]),
+            Flow::Next,
+        ),
+        "11xxx110" => (2, format!("{} ${:02X}", ALU[x as usize], n()?), Flow::Next),
+        "00xxx100" => (1, format!("INC {}", R8[x as usize]), Flow::Next),
+        "00xxx101" => (1, format!("DEC {}", R8[x as usize]), Flow::Next),
+        "00xx1001" => (1, format!("ADD HL, {}", R16[x as usize]), Flow::Next),
+        "11101000" => (2, format!("ADD SP, {}", n()? as i8), Flow::Next),
+        "00xx0011" => (1, format!("INC {}", R16[x as usize]), Flow::Next),
+        "00xx1011" => (1, format!("DEC {}", R16[x as usize]), Flow::Next),
+        "00000111" => (1, "RLCA".to_string(), Flow::Next),
+        "00010111" => (1, "RLA".to_string(), Flow::Next),
+        "00001111" => (1, "RRCA".to_string(), Flow::Next),
+        "00011111" => (1, "RRA".to_string(), Flow::Next),
+        "00100111" => (1, "DAA".to_string(), Flow::Next),
+        "00101111" => (1, "CPL".to_string(), Flow::Next),
+        "00111111" => (1, "CCF".to_string(), Flow::Next),
+        "00110111" => (1, "SCF".to_string(), Flow::Next),
+        "11000011" => {
+            let target = nn()?;
+            (3, format!("JP ${:04X}", target), Flow::Jump(target))
+        }
+        "110xx010" => {
+            let target = nn()?;
+            let cond = CONDITIONS[x as usize];
+            (
+                3,
+                format!("JP {}, ${:04X}", cond, target),
+                Flow::Branch(target),
+            )
+        }
+        "11101001" => (1, "JP HL".to_string(), Flow::Stop),
+        "00011000" => {
+            let target = relative(n()?);
+            (2, format!("JR ${:04X}", target), Flow::Jump(target))
+        }
+        "001xx000" => {
+            let target = relative(n()?);
+            let cond = CONDITIONS[x as usize];
+            (
+                2,
+                format!("JR {}, ${:04X}", cond, target),
+                Flow::Branch(target),
+            )
+        }
+        "11001101" => {
+            let target = nn()?;
+            (3, format!("CALL ${:04X}", target), Flow::Call(target))
+        }
+        "110xx100" => {
+            let target = nn()?;
+            let cond = CONDITIONS[x as usize];
+            (
+                3,
+                format!("CALL {}, ${:04X}", cond, target),
+                Flow::Call(target),
+            )
+        }
+        "11xxx111" => {
+            let target = (x as u16) * 8;
+            (1, format!("RST ${:02X}", target), Flow::Call(target))
+        }
+        "11001001" => (1, "RET".to_string(), Flow::Return),
+        "110xx000" => (
+            1,
+            format!("RET {}", CONDITIONS[x as usize]),
+            Flow::ConditionalReturn,
+        ),
+        "11011001" => (1, "RETI".to_string(), Flow::Return),
+        "11001011" => (2, decode_prefixed(n()?), Flow::Next),
+        _ => return None,
+    };
+
+    Some(Instruction {
+        addr,
+        len,
+        text,
+        flow,
+    })
+}
+
+#[bitmatch]
+fn decode_prefixed(opecode: u8) -> String {
+    #[bitmatch]
+    match opecode {
+        "00xxxyyy" => format!("{} {}", PREFIXED[x as usize], R8[y as usize]),
+        "01bbbyyy" => format!("BIT {}, {}", b, R8[y as usize]),
+        "10bbbyyy" => format!("RES {}, {}", b, R8[y as usize]),
+        "11bbbyyy" => format!("SET {}, {}", b, R8[y as usize]),
+    }
+}
+
+// エントリポイントから分岐を辿って到達できる命令と、分岐先のラベル
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub instructions: BTreeMap<u16, Instruction>,
+    pub labels: BTreeSet<u16>,
+}
+
+impl Analysis {
+    // 命令の一部として使われているバイトかどうか
+    pub fn code_map(&self, len: usize) -> Vec<bool> {
+        let mut map = vec![false; len];
+
+        for inst in self.instructions.values() {
+            for i in 0..inst.len as usize {
+                if let Some(code) = map.get_mut(inst.addr as usize + i) {
+                    *code = true;
+                }
+            }
+        }
+
+        map
+    }
+}
+
+/// 再帰的に分岐を辿ってコードを見つける。バンク切り替えは追わず、0x0000-0x7FFFのみ対象とする。
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let end = rom.len().min(ROM_END);
+    let mut analysis = Analysis::default();
+    let mut queue = ENTRY_POINTS.to_vec();
+
+    while let Some(addr) = queue.pop() {
+        if addr as usize >= end || analysis.instructions.contains_key(&addr) {
+            continue;
+        }
+
+        let inst = match decode(&rom[..end], addr) {
+            Some(inst) => inst,
+            None => continue,
+        };
+
+        let next = addr.wrapping_add(inst.len as u16);
+
+        match inst.flow {
+            Flow::Next | Flow::ConditionalReturn => queue.push(next),
+            Flow::Jump(target) => {
+                analysis.labels.insert(target);
+                queue.push(target);
+            }
+            Flow::Branch(target) | Flow::Call(target) => {
+                analysis.labels.insert(target);
+                queue.push(target);
+                queue.push(next);
+            }
+            Flow::Return | Flow::Stop => {}
+        }
+
+        analysis.instructions.insert(addr, inst);
+    }
+
+    analysis
+}
+
+/// 解析結果から、ラベル付きの逆アセンブル結果を作る。到達できなかったバイトはデータとして出力する。
+pub fn listing(rom: &[u8]) -> String {
+    const DATA_PER_LINE: usize = 8;
+
+    let end = rom.len().min(ROM_END);
+    let analysis = analyze(rom);
+    let code = analysis.code_map(end);
+    let mut out = String::new();
+    let mut addr = 0;
+
+    while addr < end {
+        if analysis.labels.contains(&(addr as u16)) {
+            let _ = writeln!(out, "L{:04X}:", addr);
+        }
+
+        if let Some(inst) = analysis.instructions.get(&(addr as u16)) {
+            let _ = writeln!(out, "    {:04X}: {}", addr, inst.text);
+
+            addr += inst.len as usize;
+            continue;
+        }
+
+        // 次の命令かラベルまでをデータとしてまとめる
+        let start = addr;
+        let mut bytes = Vec::new();
+
+        while addr < end
+            && !code[addr]
+            && bytes.len() < DATA_PER_LINE
+            && (addr == start || !analysis.labels.contains(&(addr as u16)))
+        {
+            bytes.push(format!("${:02X}", rom[addr]));
+            addr += 1;
+        }
+
+        // 命令の途中に分岐している場合等、命令の先頭以外のコードのバイト
+        if bytes.is_empty() {
+            bytes.push(format!("${:02X}", rom[addr]));
+            addr += 1;
+        }
+
+        let _ = writeln!(out, "    {:04X}: db {}", start, bytes.join(", "));
+    }
+
+    out
+}
@@ -0,0 +1,83 @@
+use alloc::string::String;
+use core::fmt;
+
+#[derive(Debug)]
+pub enum GbError {
+    IllegalOpcode(u8),
+    UnmappedRead(u16),
+    UnmappedWrite(u16),
+    UnknownRegister(u8),
+    InvalidFrameSize {
+        expected: usize,
+        actual: usize,
+    },
+    RomParse(String),
+    InvalidState(&'static str),
+    StateVersion {
+        expected: u32,
+        actual: u32,
+    },
+    StateRomMismatch,
+    UnknownPalette(usize),
+    #[cfg(feature = "std")]
+    StateFormat(bincode::Error),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+// no_stdでも使えるように、thiserrorを使わずに実装する
+impl fmt::Display for GbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GbError::IllegalOpcode(op) => write!(f, "illegal opecode {:#04X}", op),
+            GbError::UnmappedRead(addr) => write!(f, "unmapped read {:#06X}", addr),
+            GbError::UnmappedWrite(addr) => write!(f, "unmapped write {:#06X}", addr),
+            GbError::UnknownRegister(index) => write!(f, "unknown register {}", index),
+            GbError::InvalidFrameSize { expected, actual } => write!(
+                f,
+                "invalid frame size expected: {}, actual: {}",
+                expected, actual
+            ),
+            GbError::RomParse(msg) => write!(f, "{}", msg),
+            GbError::InvalidState(name) => write!(f, "invalid {} state", name),
+            GbError::StateVersion { expected, actual } => write!(
+                f,
+                "state version mismatch expected: {}, actual: {}",
+                expected, actual
+            ),
+            GbError::StateRomMismatch => write!(f, "state is for another rom"),
+            GbError::UnknownPalette(index) => write!(f, "unknown palette {}", index),
+            #[cfg(feature = "std")]
+            GbError::StateFormat(err) => fmt::Display::fmt(err, f),
+            #[cfg(feature = "std")]
+            GbError::Io(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for GbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GbError::StateFormat(err) => Some(err),
+            GbError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<bincode::Error> for GbError {
+    fn from(err: bincode::Error) -> Self {
+        GbError::StateFormat(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for GbError {
+    fn from(err: std::io::Error) -> Self {
+        GbError::Io(err)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, GbError>;
@@ -1,24 +1,302 @@
-use crate::bus::Bus;
-use crate::cpu::Cpu;
+use crate::apu::{sample_to_i16, ChannelState, SAMPLE_RATE};
+use crate::boot_palette;
+use crate::bus::{Bus, BusState, Model};
+use crate::cpu::{Cpu, CpuState};
+use crate::error::{GbError, Result};
 use crate::joypad::JoypadKey;
 use crate::mbc::new_mbc;
-use crate::ppu::Ppu;
+use crate::ppu::{ColorCorrection, Layer, Ppu, DEFAULT_DMG_PALETTE, FRAME_SIZE};
 use crate::rom::Rom;
-use anyhow::Result;
+use crate::serial::SerialSink;
+use crate::utils::MemoryFill;
+use crate::wav::WavWriter;
 use rustyline::Editor;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+
+/// 1フレーム (154ライン x 456ドット) のTサイクル数
+pub const CYCLES_PER_FRAME: u64 = 70224;
+// ステートの形式を変更した場合は上げる
+pub const STATE_VERSION: u32 = 1;
+
+pub struct Frame<'a> {
+    pub pixels: &'a [u8],
+    pub audio: Vec<f32>,
+    pub new_frame: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GbState {
+    pub rom_title: Vec<u8>,
+    pub rom_global_checksum: [u8; 2],
+    pub cycles: u64,
+    pub cpu: CpuState,
+    pub bus: BusState,
+}
 
 pub struct Gb {
     cpu: Cpu,
+    cycles: u64,
+    // run_frame等で使う画面バッファ。tickでは呼び出し側のバッファに書き込む
+    frame: Vec<u8>,
+    memory_fill: MemoryFill,
+    recorder: Option<WavWriter>,
+    save_path: Option<PathBuf>,
+    rom_title: Vec<u8>,
+    rom_global_checksum: [u8; 2],
+    // タイトルからGBCのブートROMと同じ規則で選んだパレットの番号
+    boot_palette: usize,
+}
+
+/// `Gb`の構築オプション。指定しなかった項目は`Gb::new`と同じ既定値になる。
+///
+/// ```no_run
+/// use gb::{bus::Model, gb::GbBuilder};
+///
+/// let rom = std::fs::read("rom.gbc")?;
+/// let gb = GbBuilder::new()
+///     .model(Model::Cgb)
+///     .dmg_palette([
+///         [0xFF, 0xFF, 0xFF, 0xFF],
+///         [0xAA, 0xAA, 0xAA, 0xFF],
+///         [0x55, 0x55, 0x55, 0xFF],
+///         [0x00, 0x00, 0x00, 0xFF],
+///     ])
+///     .save_path("rom.sav")
+///     .build(&rom)?;
+/// # Ok::<(), gb::error::GbError>(())
+/// ```
+pub struct GbBuilder {
+    model: Model,
+    memory_fill: MemoryFill,
+    color_correction: ColorCorrection,
+    dmg_palette: [[u8; 4]; 4],
+    boot_palette: bool,
+    save_path: Option<PathBuf>,
+    sample_rate: u32,
+    strict: bool,
+    debugger: bool,
+    editor: Option<Editor<()>>,
+}
+
+impl Default for GbBuilder {
+    fn default() -> Self {
+        Self {
+            model: Default::default(),
+            memory_fill: Default::default(),
+            color_correction: Default::default(),
+            dmg_palette: DEFAULT_DMG_PALETTE,
+            boot_palette: false,
+            save_path: None,
+            sample_rate: SAMPLE_RATE,
+            strict: true,
+            debugger: false,
+            editor: None,
+        }
+    }
+}
+
+impl GbBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn memory_fill(mut self, memory_fill: MemoryFill) -> Self {
+        self.memory_fill = memory_fill;
+        self
+    }
+
+    pub fn color_correction(mut self, mode: ColorCorrection) -> Self {
+        self.color_correction = mode;
+        self
+    }
+
+    pub fn dmg_palette(mut self, palette: [[u8; 4]; 4]) -> Self {
+        self.dmg_palette = palette;
+        self
+    }
+
+    /// trueの場合は`dmg_palette`の代わりにタイトルから選んだGBCのブートパレットを使う
+    pub fn boot_palette(mut self, on: bool) -> Self {
+        self.boot_palette = on;
+        self
+    }
+
+    /// カートリッジRAMの保存先。存在すれば構築時に読み込み、`Gb::save_ram`で書き出す。
+    pub fn save_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.save_path = Some(path.into());
+        self
+    }
+
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = rate;
+        self
+    }
+
+    /// falseの場合はヘッダチェックサムとROMサイズを検証しない (`Rom::new_unchecked`)
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// trueの場合はブレーク時にデバッガのREPLに入る。既定では無効で、REPLには一切入らない
+    pub fn debugger(mut self, on: bool) -> Self {
+        self.debugger = on;
+        self
+    }
+
+    pub fn editor(mut self, rl: Editor<()>) -> Self {
+        self.editor = Some(rl);
+        self
+    }
+
+    pub fn build(self, rom_bytes: &[u8]) -> Result<Gb> {
+        let mut reader = Cursor::new(rom_bytes);
+        let rom = if self.strict {
+            Rom::new(&mut reader)?
+        } else {
+            Rom::new_unchecked(&mut reader)?
+        };
+
+        let rl = self.editor.unwrap_or_else(Editor::new);
+        let mut gb = Gb::with_memory_fill(rom, rl, self.memory_fill);
+
+        gb.set_model(self.model);
+        gb.set_debugger_enabled(self.debugger);
+        gb.set_color_correction(self.color_correction);
+        gb.cpu.bus.ppu.set_dmg_palette(self.dmg_palette);
+
+        if self.boot_palette {
+            gb.apply_boot_palette();
+        }
+
+        gb.cpu.bus.apu.set_sample_rate(self.sample_rate);
+
+        if let Some(path) = &self.save_path {
+            if path.exists() {
+                gb.cpu.bus.load_cartridge_ram(&fs::read(path)?);
+            }
+        }
+
+        gb.save_path = self.save_path;
+
+        Ok(gb)
+    }
 }
 
 impl Gb {
     pub fn new(rom: Rom, rl: Editor<()>) -> Self {
+        Self::with_memory_fill(rom, rl, MemoryFill::default())
+    }
+
+    /// WRAM/HRAM/VRAMの電源投入時の内容を指定して生成する。
+    /// 同じシードの`MemoryFill::Random`からは常に同じ内容が得られる。
+    pub fn with_memory_fill(rom: Rom, rl: Editor<()>, memory_fill: MemoryFill) -> Self {
+        let rom_title = rom.title.to_vec();
+        let rom_global_checksum = rom.global_checksum;
+        let boot_palette = boot_palette::lookup(&rom);
         let mbc = new_mbc(rom);
         let ppu = Ppu::new();
-        let bus = Bus::new(ppu, mbc);
+        let mut bus = Bus::new(ppu, mbc);
+        bus.fill_memory(memory_fill);
         let cpu = Cpu::new(bus, rl);
 
-        Gb { cpu }
+        Gb {
+            cpu,
+            cycles: 0,
+            frame: vec![0; FRAME_SIZE],
+            memory_fill,
+            recorder: None,
+            save_path: None,
+            rom_title,
+            rom_global_checksum,
+            boot_palette,
+        }
+    }
+
+    pub fn memory_fill(&self) -> MemoryFill {
+        self.memory_fill
+    }
+
+    /// カートリッジRAMを`GbBuilder::save_path`で指定したファイルに書き出す。
+    /// 保存先が指定されていない場合は何もしない。
+    pub fn save_ram(&self) -> Result<()> {
+        if let Some(path) = &self.save_path {
+            fs::write(path, self.cpu.bus.cartridge_ram())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> GbState {
+        GbState {
+            rom_title: self.rom_title.clone(),
+            rom_global_checksum: self.rom_global_checksum,
+            cycles: self.cycles,
+            cpu: self.cpu.state(),
+            bus: self.cpu.bus.snapshot(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &GbState) -> Result<()> {
+        if state.rom_title != self.rom_title
+            || state.rom_global_checksum != self.rom_global_checksum
+        {
+            return Err(GbError::StateRomMismatch);
+        }
+
+        // 途中で失敗した場合は元の状態に戻す
+        let backup = self.snapshot();
+
+        if let Err(err) = self.apply_state(state) {
+            self.apply_state(&backup)?;
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn apply_state(&mut self, state: &GbState) -> Result<()> {
+        self.cpu.bus.restore(&state.bus)?;
+        self.cpu.set_state(state.cpu.clone());
+        self.cycles = state.cycles;
+
+        Ok(())
+    }
+
+    /// 先頭に`STATE_VERSION`を付けてマシン全体の状態を書き出す
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        bincode::serialize_into(&mut writer, &STATE_VERSION)?;
+        bincode::serialize_into(&mut writer, &self.snapshot())?;
+
+        Ok(())
+    }
+
+    pub fn load_state<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+
+        if version != STATE_VERSION {
+            return Err(GbError::StateVersion {
+                expected: STATE_VERSION,
+                actual: version,
+            });
+        }
+
+        let state: GbState = bincode::deserialize_from(&mut reader)?;
+
+        self.restore(&state)
     }
 
     pub fn reset(&mut self) -> Result<()> {
@@ -33,20 +311,293 @@ impl Gb {
         self.cpu.bus.joypad.release(key)
     }
 
+    pub fn set_debugger_enabled(&mut self, on: bool) {
+        self.cpu.debugger = on;
+    }
+
+    pub fn debugger_enabled(&self) -> bool {
+        self.cpu.debugger
+    }
+
     pub fn debug_break(&mut self) -> Result<()> {
         self.cpu.debug_break();
 
         Ok(())
     }
 
-    pub fn tick(&mut self) -> Result<()> {
-        self.cpu.tick()?;
-        self.cpu.bus.tick()?;
+    pub fn vram(&self, bank: u8) -> &[u8] {
+        // DMGはVRAMが1バンクのみ
+        match bank {
+            0 => self.cpu.bus.ppu.vram(),
+            _ => &[],
+        }
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        self.cpu.bus.ppu.oam()
+    }
+
+    pub fn io(&self) -> [u8; 0x80] {
+        self.cpu.bus.io()
+    }
+
+    /// 256x256のBG全体に表示範囲の枠を重ねた画像 (RGBA8)。outはFULL_BG_SIZEバイト
+    pub fn render_full_bg(&self, out: &mut [u8]) -> Result<()> {
+        self.cpu.bus.ppu.render_full_bg(out)
+    }
+
+    pub fn render_full_window(&self, out: &mut [u8]) -> Result<()> {
+        self.cpu.bus.ppu.render_full_window(out)
+    }
+
+    pub fn frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.cpu.bus.ppu.frame_ready)
+    }
+
+    /// 録音中に書き込みに失敗した場合は録音を止めてエラーを返す。その回のサンプルは失われる
+    pub fn drain_samples(&mut self) -> Result<Vec<f32>> {
+        let samples = self.cpu.bus.apu.drain_samples();
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            let pcm: Vec<i16> = samples.iter().map(|&s| sample_to_i16(s)).collect();
+
+            if let Err(err) = recorder.write(&pcm) {
+                self.recorder = None;
+
+                return Err(err);
+            }
+        }
+
+        Ok(samples)
+    }
+
+    /// 以降に取り出される音声を16bitステレオのWAVファイルに記録する。
+    /// 記録中に呼んだ場合は前のファイルを確定してから新しいファイルに切り替える。
+    pub fn record_audio_wav<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(WavWriter::create(path, self.cpu.bus.apu.sample_rate(), 2)?);
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_channel_enabled(&mut self, ch: u8, on: bool) {
+        self.cpu.bus.apu.set_channel_enabled(ch, on)
+    }
+
+    pub fn channel_enabled(&self, ch: u8) -> bool {
+        self.cpu.bus.apu.channel_enabled(ch)
+    }
+
+    pub fn channel_state(&self, ch: u8) -> Option<ChannelState> {
+        self.cpu.bus.apu.channel_state(ch)
+    }
+
+    /// LD B,Bをソフトウェアブレークとして扱う。当たると`run_cycles`と`run_until`が止まり、
+    /// `software_break_hit`で取り出すまでは呼び出す度にすぐ止まる
+    pub fn set_software_break(&mut self, on: bool) {
+        self.cpu.software_break = on;
+    }
+
+    /// シリアルポートから送信されたバイトを受け取るSinkを設定する。
+    /// 設定しない場合はserial_outputに溜める
+    pub fn set_serial_sink(&mut self, sink: Box<dyn SerialSink + Send>) {
+        self.cpu.bus.set_serial_sink(sink);
+    }
+
+    /// Sinkが設定されていない間にシリアルポートから送信された文字列
+    pub fn serial_output(&self) -> &str {
+        self.cpu.bus.serial_output()
+    }
+
+    pub fn software_break_hit(&mut self) -> bool {
+        self.cpu.take_software_break_hit()
+    }
+
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        self.cpu.bus.ppu.toggle_layer(layer)
+    }
+
+    pub fn set_color_correction(&mut self, mode: ColorCorrection) {
+        self.cpu.bus.ppu.set_color_correction(mode);
+    }
+
+    /// タイトルのチェックサムから選ばれる、ブートROMのパレットの組み合わせの番号
+    pub fn palette(&self) -> usize {
+        self.boot_palette
+    }
+
+    /// GBCのブートROMがこのソフトに割り当てるパレットを使う
+    pub fn apply_boot_palette(&mut self) {
+        // lookupは常に表の範囲内の番号を返す
+        let _ = self.set_dmg_palette_by_index(self.boot_palette);
+    }
+
+    /// ブートROMのパレットの組み合わせを番号で選ぶ (`boot_palette::BUTTON_PALETTES`も参照)
+    pub fn set_dmg_palette_by_index(&mut self, index: usize) -> Result<()> {
+        let palette = boot_palette::palette(index).ok_or(GbError::UnknownPalette(index))?;
+
+        self.cpu
+            .bus
+            .ppu
+            .set_dmg_palettes(palette.bg, palette.obj0, palette.obj1);
 
         Ok(())
     }
 
-    pub fn render(&mut self, frame: &mut [u8]) -> Result<()> {
-        self.cpu.bus.ppu.render(frame)
+    pub fn set_model(&mut self, model: Model) {
+        self.cpu.bus.set_model(model);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    /// レジスタに加えて、IME、EIの保留、HALT等の外から見えない状態も含むCPUの状態
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    /// テストの準備等のためにCPUの状態を直接設定する。Fの下位4ビットは常に0になる
+    pub fn set_cpu_state(&mut self, state: &CpuState) {
+        self.cpu.set_state(state.clone());
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.cpu.instructions()
+    }
+
+    /// 有効にすると命令毎の実行回数を数え始める (0から数え直す)
+    pub fn set_opcode_histogram(&mut self, on: bool) {
+        self.cpu.set_opcode_histogram(on);
+    }
+
+    /// 0x000-0x0FFは通常の命令、0x100-0x1FFはCBプレフィックス付きの命令の実行回数。
+    /// 無効の場合はすべて0になる。
+    pub fn opcode_histogram(&self) -> [u64; 512] {
+        self.cpu.opcode_histogram().copied().unwrap_or([0; 512])
+    }
+
+    /// `frame` はフロントエンドが所有する RGBA8 の画面バッファ。
+    /// 160x144 の行優先で `FRAME_SIZE` バイトちょうどでなければならない。
+    /// HBlank 毎に 1 ライン分ずつ直接書き込まれ、VBlank 時点で 1 フレーム分が揃う。
+    /// `run_frame` 等が使う内部のバッファには書き込まれない。
+    /// 1回の呼び出しで進むTサイクル数は一定ではないため、経過は`cycles`で測る。
+    pub fn tick(&mut self, frame: &mut [u8]) -> Result<()> {
+        if frame.len() != FRAME_SIZE {
+            return Err(GbError::InvalidFrameSize {
+                expected: FRAME_SIZE,
+                actual: frame.len(),
+            });
+        }
+
+        self.advance()?;
+        self.cpu.bus.ppu.put_line(frame)
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.advance()?;
+        self.cpu.bus.ppu.put_line(&mut self.frame)
+    }
+
+    // CPUを1回進め、経過したTサイクル数を`cycles`に加える
+    fn advance(&mut self) -> Result<()> {
+        let accessed = self.cpu.tick()?;
+
+        // メモリアクセスの分は既にバスが進んでいるため、それ以外のサイクルだけバスを進める
+        let elapsed = if accessed == 0 {
+            self.cpu.bus.tick()?;
+            1
+        } else {
+            accessed as u64
+        };
+
+        self.cycles = self.cycles.wrapping_add(elapsed);
+
+        Ok(())
+    }
+
+    /// 1フレーム分 (次のVBlankまで、最大70224サイクル) 進め、画面と音声をまとめて返す。
+    /// 組み込み用途ではこれを表示のリフレッシュ毎に1回呼ぶのが基本となる。
+    ///
+    /// ```no_run
+    /// # use gb::{gb::Gb, rom::Rom};
+    /// # use std::{fs::File, io::BufReader};
+    /// # let rom = Rom::new(&mut BufReader::new(File::open("rom.gb")?))?;
+    /// # let mut gb = Gb::new(rom, rustyline::Editor::new());
+    /// # gb.reset()?;
+    /// loop {
+    ///     let frame = gb.run_frame()?;
+    ///     // frame.pixels を描画し、frame.audio を再生する
+    /// }
+    /// # Ok::<(), gb::error::GbError>(())
+    /// ```
+    pub fn run_frame(&mut self) -> Result<Frame<'_>> {
+        let mut new_frame = false;
+        let end = self.cycles.wrapping_add(CYCLES_PER_FRAME);
+
+        while self.cycles < end {
+            self.step()?;
+
+            if self.frame_ready() {
+                new_frame = true;
+                break;
+            }
+        }
+
+        let audio = self.drain_samples()?;
+
+        Ok(Frame {
+            pixels: &self.frame,
+            audio,
+            new_frame,
+        })
+    }
+
+    /// ちょうど`n`サイクル進める。命令の途中で止めないため、
+    /// 実行中の命令が終わるまで進めた超過分のサイクル数を返す。
+    /// ソフトウェアブレーク (LD B,B) に当たった場合はその命令の後で止まり、0を返す。
+    pub fn run_cycles(&mut self, n: u64) -> Result<u64> {
+        let end = self.cycles.wrapping_add(n);
+
+        while self.cycles < end && !self.cpu.software_break_pending() {
+            self.step()?;
+        }
+
+        while !self.cpu.at_instruction_boundary() {
+            self.step()?;
+        }
+
+        Ok(self.cycles.saturating_sub(end))
+    }
+
+    /// `pred`を満たすまで最大`max_cycles`サイクル進める。
+    /// ソフトウェアブレーク (LD B,B) に当たった場合もそこで止まる
+    pub fn run_until(&mut self, max_cycles: u64, pred: impl Fn(&Gb) -> bool) -> Result<bool> {
+        let end = self.cycles.wrapping_add(max_cycles);
+
+        while self.cycles < end {
+            if pred(self) {
+                return Ok(true);
+            }
+
+            self.step()?;
+
+            if self.cpu.software_break_pending() {
+                break;
+            }
+        }
+
+        Ok(pred(self))
     }
 }
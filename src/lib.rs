@@ -1,9 +1,25 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod apu;
+pub mod boot_palette;
 pub mod bus;
 pub mod cpu;
+pub mod disasm;
+pub mod error;
+#[cfg(feature = "std")]
 pub mod gb;
 pub mod joypad;
 pub mod mbc;
 pub mod ppu;
 pub mod rom;
+pub mod scheduler;
+pub mod serial;
 pub mod timer;
 pub mod utils;
+#[cfg(feature = "std")]
+pub mod wav;
+
+#[cfg(feature = "std")]
+pub use crate::gb::CYCLES_PER_FRAME;
@@ -1,11 +1,23 @@
+use anyhow::{anyhow, Result};
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio")]
+use gb::apu::SAMPLE_RATE;
+use gb::disasm;
 use gb::gb::Gb;
 use gb::joypad::JoypadKey;
+use gb::ppu::{Layer, FRAME_SIZE};
 use gb::rom::Rom;
+use gb::CYCLES_PER_FRAME;
 use pixels::{Pixels, SurfaceTexture};
 use rustyline::Editor;
+#[cfg(feature = "audio")]
+use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -15,7 +27,173 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-fn main() {
+// コアのno_std対応でeprintln!の代わりにlogを使うようになったため、標準エラー出力に流す
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("{}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+const JOYPAD_KEYS: [(VirtualKeyCode, JoypadKey); 8] = [
+    (VirtualKeyCode::Z, JoypadKey::A),
+    (VirtualKeyCode::X, JoypadKey::B),
+    (VirtualKeyCode::C, JoypadKey::Select),
+    (VirtualKeyCode::V, JoypadKey::Start),
+    (VirtualKeyCode::Up, JoypadKey::Up),
+    (VirtualKeyCode::Down, JoypadKey::Down),
+    (VirtualKeyCode::Left, JoypadKey::Left),
+    (VirtualKeyCode::Right, JoypadKey::Right),
+];
+
+const LAYER_KEYS: [(VirtualKeyCode, Layer); 3] = [
+    (VirtualKeyCode::Key1, Layer::Background),
+    (VirtualKeyCode::Key2, Layer::Window),
+    (VirtualKeyCode::Key3, Layer::Sprites),
+];
+
+#[cfg(feature = "audio")]
+const MUTE_KEY: VirtualKeyCode = VirtualKeyCode::M;
+
+// --audio-sync で保つ出力待ちのサンプル数 (ステレオで約50ms分)
+#[cfg(feature = "audio")]
+const AUDIO_SYNC_TARGET: usize = SAMPLE_RATE as usize * 2 / 20;
+
+// 映像に合わせて進める場合に溜める上限。超えた分は古いものから捨てる (約200ms分)
+#[cfg(feature = "audio")]
+const AUDIO_QUEUE_LIMIT: usize = SAMPLE_RATE as usize * 2 / 5;
+
+// エミュレーションのスレッドが積み、cpalのコールバックが取り出すステレオのサンプル
+#[cfg(feature = "audio")]
+type AudioQueue = Arc<Mutex<VecDeque<f32>>>;
+
+// 既定の出力デバイスを44.1kHzステレオで開く
+#[cfg(feature = "audio")]
+fn open_audio() -> Result<(cpal::Stream, AudioQueue)> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| anyhow!("no output device"))?;
+    let config = cpal::StreamConfig {
+        channels: 2,
+        sample_rate: cpal::SampleRate(SAMPLE_RATE),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let queue = AudioQueue::default();
+    let output = queue.clone();
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            let mut output = output.lock().unwrap();
+
+            // 足りない分は無音にする
+            for sample in data.iter_mut() {
+                *sample = output.pop_front().unwrap_or(0.0);
+            }
+        },
+        |err| eprintln!("audio output error: {}", err),
+    )?;
+
+    stream.play()?;
+
+    Ok((stream, queue))
+}
+
+const LETTER_KEYS: [VirtualKeyCode; 26] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+];
+
+fn main() -> Result<()> {
+    log::set_logger(&LOGGER).map_err(|err| anyhow!("{}", err))?;
+    log::set_max_level(log::LevelFilter::Debug);
+
+    let args = env::args().collect::<Vec<String>>();
+
+    let state_on_exit = args.iter().any(|arg| arg == "--state-on-exit");
+    let debugger = !args.iter().any(|arg| arg == "--no-debugger");
+    let audio_sync = args.iter().any(|arg| arg == "--audio-sync");
+    let break_key = match args.iter().find_map(|arg| arg.strip_prefix("--break-key=")) {
+        Some(name) => parse_key(name)?,
+        None => VirtualKeyCode::B,
+    };
+    // --boot-palette はタイトルから選び、--boot-palette=<番号> は番号で選ぶ
+    let boot_palette = match args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--boot-palette="))
+    {
+        Some(index) => Some(Some(index.parse::<usize>()?)),
+        None if args.iter().any(|arg| arg == "--boot-palette") => Some(None),
+        None => None,
+    };
+    let args = args
+        .into_iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect::<Vec<String>>();
+
+    match args[1].as_str() {
+        subcommand @ "info" | subcommand @ "checksum" => {
+            // 壊れたダンプも報告できるように、ヘッダの検証はせずに読み込む
+            let path = args
+                .get(2)
+                .ok_or_else(|| anyhow!("usage: gb {} <rom>", subcommand))?;
+            let mut reader = BufReader::new(File::open(path)?);
+            let rom = Rom::new_unchecked(&mut reader)?;
+
+            if subcommand == "info" {
+                println!("{:#?}", rom);
+            }
+
+            println!("{:#?}", rom.verify());
+
+            return Ok(());
+        }
+        "disasm" => {
+            let path = args
+                .get(2)
+                .ok_or_else(|| anyhow!("usage: gb disasm <rom>"))?;
+            let data = std::fs::read(path)?;
+
+            print!("{}", disasm::listing(&data));
+
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
@@ -31,25 +209,113 @@ fn main() {
     let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
     let mut pixels = Pixels::new(160, 144, surface_texture).unwrap();
 
-    let args = env::args().collect::<Vec<String>>();
+    let rom_path = PathBuf::from(&args[1]);
+    let resume_path = rom_path.with_extension("resume");
+
+    let mut reader = BufReader::new(File::open(&rom_path)?);
+    let rom = Rom::new(&mut reader)?;
 
-    let mut reader = BufReader::new(File::open(args[1].clone()).unwrap());
-    let rom = Rom::new(&mut reader).unwrap();
+    let mut rl = Editor::<()>::new();
 
-    let rl = Editor::<()>::new();
+    let resume = state_on_exit
+        && resume_path.exists()
+        && matches!(
+            rl.readline(&format!("resume from {}? [Y/n] ", resume_path.display())),
+            Ok(line) if !line.trim().eq_ignore_ascii_case("n")
+        );
 
     let gb = Arc::new(Mutex::new(Gb::new(rom, rl)));
+    let frame = Arc::new(Mutex::new(vec![0; FRAME_SIZE]));
+    let paused = Arc::new(AtomicBool::new(false));
+
+    // ライブラリの既定ではデバッガは無効のため、--no-debugger が無ければ有効にする
+    gb.lock().unwrap().set_debugger_enabled(debugger);
+
+    #[cfg(feature = "audio")]
+    let (_stream, audio) = match open_audio() {
+        Ok((stream, queue)) => (Some(stream), Some(queue)),
+        Err(err) => {
+            eprintln!(
+                "failed to open audio output ({}), running without sound",
+                err
+            );
+            (None, None)
+        }
+    };
+    #[cfg(feature = "audio")]
+    let muted = Arc::new(AtomicBool::new(false));
+    #[cfg(not(feature = "audio"))]
+    if audio_sync {
+        eprintln!("--audio-sync needs the audio feature, pacing by video instead");
+    }
+
+    match boot_palette {
+        Some(Some(index)) => gb.lock().unwrap().set_dmg_palette_by_index(index)?,
+        Some(None) => gb.lock().unwrap().apply_boot_palette(),
+        None => {}
+    }
 
     {
         let gb = gb.clone();
+        let frame = frame.clone();
+        let paused = paused.clone();
+        #[cfg(feature = "audio")]
+        let audio = audio.clone();
+        #[cfg(feature = "audio")]
+        let muted = muted.clone();
 
         gb.lock().unwrap().reset().unwrap();
 
+        if resume {
+            if let Err(err) = gb.lock().unwrap().load_state(&resume_path) {
+                eprintln!("failed to resume ({}), starting fresh", err);
+            }
+        }
+
         thread::spawn(move || loop {
             let time = Instant::now();
 
-            for _ in 0..70224 {
-                gb.lock().unwrap().tick().unwrap();
+            if paused.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1000 / 60));
+                continue;
+            }
+
+            {
+                let mut gb = gb.lock().unwrap();
+                let mut frame = frame.lock().unwrap();
+                let end = gb.cycles() + CYCLES_PER_FRAME;
+
+                while gb.cycles() < end {
+                    gb.tick(&mut frame).unwrap();
+                }
+
+                #[cfg(feature = "audio")]
+                if let Some(queue) = audio.as_ref() {
+                    let samples = gb.drain_samples().unwrap();
+                    let mut queue = queue.lock().unwrap();
+
+                    if muted.load(Ordering::Relaxed) {
+                        queue.clear();
+                    } else {
+                        queue.extend(samples);
+
+                        let excess = queue.len().saturating_sub(AUDIO_QUEUE_LIMIT);
+                        queue.drain(..excess);
+                    }
+                }
+            }
+
+            // --audio-sync では出力待ちのサンプルが目標まで減るのを待って次のフレームに進む。
+            // 映像の間隔は多少揺れるが、音声が途切れない。ミュート中は映像に合わせる
+            #[cfg(feature = "audio")]
+            if let Some(queue) = audio.as_ref() {
+                if audio_sync && !muted.load(Ordering::Relaxed) {
+                    while queue.lock().unwrap().len() > AUDIO_SYNC_TARGET {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+
+                    continue;
+                }
             }
 
             let elapsed = time.elapsed().as_millis();
@@ -73,8 +339,13 @@ fn main() {
                 } => {
                     *control_flow = ControlFlow::Exit;
                 }
+                Event::LoopDestroyed if state_on_exit => {
+                    if let Err(err) = gb.lock().unwrap().save_state(&resume_path) {
+                        eprintln!("failed to save {}: {}", resume_path.display(), err);
+                    }
+                }
                 Event::RedrawRequested(_) => {
-                    gb.lock().unwrap().render(pixels.get_frame()).unwrap();
+                    pixels.get_frame().copy_from_slice(&frame.lock().unwrap());
                     pixels.render().unwrap();
                 }
                 _ => {}
@@ -95,22 +366,33 @@ fn main() {
                             return;
                         }
 
-                        if input.key_pressed(VirtualKeyCode::B) {
-                            gb.lock().unwrap().debug_break().unwrap();
+                        if input.key_pressed(break_key) {
+                            // デバッガが無効の場合はREPLでブロックせず、一時停止を切り替える
+                            if debugger {
+                                gb.lock().unwrap().debug_break().unwrap();
+                            } else {
+                                let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+
+                                println!("{}", if was_paused { "resumed" } else { "paused" });
+                            }
+                        }
+
+                        #[cfg(feature = "audio")]
+                        if input.key_pressed(MUTE_KEY) {
+                            let was_muted = muted.fetch_xor(true, Ordering::Relaxed);
+
+                            println!("{}", if was_muted { "unmuted" } else { "muted" });
                         }
 
-                        for (input_key, joypad_key) in [
-                            (VirtualKeyCode::Z, JoypadKey::A),
-                            (VirtualKeyCode::X, JoypadKey::B),
-                            (VirtualKeyCode::C, JoypadKey::Select),
-                            (VirtualKeyCode::V, JoypadKey::Start),
-                            (VirtualKeyCode::Up, JoypadKey::Up),
-                            (VirtualKeyCode::Down, JoypadKey::Down),
-                            (VirtualKeyCode::Left, JoypadKey::Left),
-                            (VirtualKeyCode::Right, JoypadKey::Right),
-                        ]
-                        .iter()
-                        {
+                        for (input_key, layer) in LAYER_KEYS.iter() {
+                            if input.key_pressed(*input_key) {
+                                let visible = gb.lock().unwrap().toggle_layer(*layer);
+
+                                println!("{:?}: {}", layer, if visible { "on" } else { "off" });
+                            }
+                        }
+
+                        for (input_key, joypad_key) in JOYPAD_KEYS.iter() {
                             if input.key_pressed(*input_key) {
                                 gb.lock().unwrap().press(*joypad_key);
                             }
@@ -130,3 +412,42 @@ fn main() {
         });
     }
 }
+
+// --break-key に指定できるキー。ジョイパッド等に割り当て済みのキーは除く
+fn parse_key(name: &str) -> Result<VirtualKeyCode> {
+    let name = name.to_ascii_uppercase();
+
+    let key = match name.as_bytes() {
+        [letter @ b'A'..=b'Z'] => LETTER_KEYS[(letter - b'A') as usize],
+        _ => match name.as_str() {
+            "F1" => VirtualKeyCode::F1,
+            "F2" => VirtualKeyCode::F2,
+            "F3" => VirtualKeyCode::F3,
+            "F4" => VirtualKeyCode::F4,
+            "F5" => VirtualKeyCode::F5,
+            "F6" => VirtualKeyCode::F6,
+            "F7" => VirtualKeyCode::F7,
+            "F8" => VirtualKeyCode::F8,
+            "F9" => VirtualKeyCode::F9,
+            "F10" => VirtualKeyCode::F10,
+            "F11" => VirtualKeyCode::F11,
+            "F12" => VirtualKeyCode::F12,
+            "PAUSE" => VirtualKeyCode::Pause,
+            "SPACE" => VirtualKeyCode::Space,
+            "TAB" => VirtualKeyCode::Tab,
+            _ => return Err(anyhow!("unknown key {}", name)),
+        },
+    };
+
+    let bound = JOYPAD_KEYS.iter().any(|&(k, _)| k == key)
+        || LAYER_KEYS.iter().any(|&(k, _)| k == key)
+        || key == VirtualKeyCode::Escape;
+    #[cfg(feature = "audio")]
+    let bound = bound || key == MUTE_KEY;
+
+    if bound {
+        return Err(anyhow!("{} is already bound", name));
+    }
+
+    Ok(key)
+}
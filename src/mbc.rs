@@ -1,16 +1,46 @@
-use crate::rom::{MbcType, Rom};
-use anyhow::Result;
-use std::cmp::max;
+use crate::bus::OPEN_BUS;
+use crate::error::{GbError, Result};
+use crate::rom::{MbcType, Rom, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::max;
+use serde::{Deserialize, Serialize};
 
 pub trait Mbc {
     fn read(&self, addr: u16) -> Result<u8>;
     fn write(&mut self, addr: u16, val: u8) -> Result<()>;
+    fn ram(&self) -> &[u8];
+    fn load_ram(&mut self, data: &[u8]);
+    fn snapshot(&self) -> MbcState;
+    fn restore(&mut self, state: &MbcState) -> Result<()>;
+    // 1Tサイクル進める (RTC等の時間で動く機能を持つMBCのみ実装する)
+    fn tick(&mut self) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MbcState {
+    pub ram: Vec<u8>,
+    // バンク切り替え等のレジスタ (MBCの種類毎に内容が異なる)
+    pub registers: Vec<u8>,
+}
+
+// セーブデータのサイズが異なる場合は先頭から読み込める分だけ読み込む
+fn copy_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+
+    ram[..len].copy_from_slice(&data[..len]);
 }
 
 pub fn new_mbc(rom: Rom) -> Box<dyn Mbc + Send> {
     match rom.mbc_type {
         MbcType::RomOnly => Box::new(RomOnly::new(rom)),
         MbcType::Mbc1 | MbcType::Mbc1Ram | MbcType::Mbc1RamBattery => Box::new(Mbc1::new(rom)),
+        MbcType::Mbc3
+        | MbcType::Mbc3Ram
+        | MbcType::Mbc3RamBattery
+        | MbcType::Mbc3TimerBattery
+        | MbcType::Mbc3TimerRamBattery => Box::new(Mbc3::new(rom)),
         t => {
             unimplemented!("unimplemented mbc: {:?}", t);
         }
@@ -34,21 +64,61 @@ impl RomOnly {
 impl Mbc for RomOnly {
     fn read(&self, addr: u16) -> Result<u8> {
         if addr >= 0xA000 {
-            return Ok(self.ram[(addr - 0xA000) as usize]);
+            return self
+                .ram
+                .get((addr - 0xA000) as usize)
+                .copied()
+                .ok_or(GbError::UnmappedRead(addr));
         }
 
-        Ok(self.rom.data[addr as usize])
+        // ROMサイズを超える範囲はオープンバス
+        Ok(self
+            .rom
+            .data
+            .get(addr as usize)
+            .copied()
+            .unwrap_or(OPEN_BUS))
     }
 
     fn write(&mut self, addr: u16, val: u8) -> Result<()> {
         if addr >= 0xA000 {
-            self.ram[(addr - 0xA000) as usize] = val;
+            let byte = self
+                .ram
+                .get_mut((addr - 0xA000) as usize)
+                .ok_or(GbError::UnmappedWrite(addr))?;
+
+            *byte = val;
 
             return Ok(());
         }
 
         Ok(())
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+
+    fn snapshot(&self) -> MbcState {
+        MbcState {
+            ram: self.ram.to_vec(),
+            registers: Vec::new(),
+        }
+    }
+
+    fn restore(&mut self, state: &MbcState) -> Result<()> {
+        if state.ram.len() != self.ram.len() {
+            return Err(GbError::InvalidState("mbc"));
+        }
+
+        self.ram.copy_from_slice(&state.ram);
+
+        Ok(())
+    }
 }
 
 enum Mbc1SelectMode {
@@ -58,9 +128,11 @@ enum Mbc1SelectMode {
 
 pub struct Mbc1 {
     rom: Rom,
-    ram: [u8; 32 * 1024],
+    ram: Vec<u8>,
+    // BANK1 (0x2000-0x3FFF、下位5bit)
     rom_bank: u8,
-    ram_bank: u8,
+    // BANK2 (0x4000-0x5FFF、2bit)。ROMバンクの上位ビット、モード1ではRAMバンクにも使う
+    bank2: u8,
 
     enable_ram: bool,
     select_mode: Mbc1SelectMode,
@@ -68,45 +140,74 @@ pub struct Mbc1 {
 
 impl Mbc1 {
     pub fn new(rom: Rom) -> Self {
+        // RAMは2KiB(部分的な1バンク), 8KiB(1バンク), 32KiB(4バンク)のいずれか
+        let ram = vec![0; rom.ram_size];
+
         Mbc1 {
             rom,
-            ram: [0; 32 * 1024],
+            ram,
             rom_bank: 1,
-            ram_bank: 0,
-            enable_ram: true,
+            bank2: 0,
+            enable_ram: false,
             select_mode: Mbc1SelectMode::ROM,
         }
     }
 
     fn read_rom_from_bank(&self, addr: u16) -> Result<u8> {
-        let base_addr = ((self.rom_bank as u64) * 16 * 1024) as usize;
+        // 実際のバンク数を超える上位ビットはカートリッジのアドレス線に繋がっていない
+        let bank = ((self.bank2 << 5) | self.rom_bank) as usize % self.rom.rom_banks();
+        let base_addr = bank * ROM_BANK_SIZE;
         let index_addr = (addr - 0x4000) as usize;
-        Ok(self.rom.data[base_addr + index_addr])
+
+        // ROMサイズを超えるバンクはオープンバス
+        Ok(self
+            .rom
+            .data
+            .get(base_addr + index_addr)
+            .copied()
+            .unwrap_or(OPEN_BUS))
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        // RAMバンクの切り替えは32KiB以上のRAMを持つカートリッジのモード1でのみ有効
+        let bank = match self.select_mode {
+            Mbc1SelectMode::RAM if self.ram.len() >= 32 * 1024 => {
+                self.bank2 as usize % self.rom.ram_banks()
+            }
+            _ => 0,
+        };
+
+        bank * RAM_BANK_SIZE + (addr - 0xA000) as usize
     }
 
     fn read_ram_from_bank(&self, addr: u16) -> Result<u8> {
         if !self.enable_ram {
-            eprintln!("disabled ram read");
+            log::debug!("disabled ram read");
 
-            return Ok(0);
+            // 無効時はオープンバス
+            return Ok(OPEN_BUS);
         }
 
-        let base_addr = ((self.ram_bank as u64) * 8 * 1024) as usize;
-        let index_addr = (addr - 0xA000) as usize;
-        Ok(self.ram[base_addr + index_addr])
+        // RAMサイズを超える範囲もオープンバス
+        Ok(self
+            .ram
+            .get(self.ram_offset(addr))
+            .copied()
+            .unwrap_or(OPEN_BUS))
     }
 
     fn write_ram_into_bank(&mut self, addr: u16, val: u8) -> Result<()> {
         if !self.enable_ram {
-            eprintln!("disabled ram write");
+            log::debug!("disabled ram write");
 
             return Ok(());
         }
 
-        let base_addr = ((self.ram_bank as u16) * 8 * 1024) as usize;
-        let index_addr = (addr - 0xA000) as usize;
+        let offset = self.ram_offset(addr);
 
-        self.ram[base_addr + index_addr] = val;
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = val;
+        }
 
         Ok(())
     }
@@ -115,10 +216,15 @@ impl Mbc1 {
 impl Mbc for Mbc1 {
     fn read(&self, addr: u16) -> Result<u8> {
         match addr {
-            0x0000..=0x3FFF => Ok(self.rom.data[addr as usize]),
+            0x0000..=0x3FFF => Ok(self
+                .rom
+                .data
+                .get(addr as usize)
+                .copied()
+                .unwrap_or(OPEN_BUS)),
             0x4000..=0x7FFF => self.read_rom_from_bank(addr),
             0xA000..=0xBFFF => self.read_ram_from_bank(addr),
-            _ => Ok(0),
+            _ => Err(GbError::UnmappedRead(addr)),
         }
     }
 
@@ -143,31 +249,313 @@ impl Mbc for Mbc1 {
 
                 Ok(())
             }
-            0x4000..=0x5FFF => match self.select_mode {
-                Mbc1SelectMode::ROM => {
-                    let bank_high = val & 0b00000011;
+            0x4000..=0x5FFF => {
+                // モードに関わらず同じレジスタに書き込み、使い方だけがモードで変わる
+                self.bank2 = val & 0b00000011;
 
-                    self.rom_bank |= bank_high << 5;
+                Ok(())
+            }
+            0x6000..=0x7FFF => {
+                self.select_mode = match val & 0x01 {
+                    0x01 => Mbc1SelectMode::RAM,
+                    _ => Mbc1SelectMode::ROM,
+                };
 
-                    Ok(())
+                Ok(())
+            }
+            addr => self.write_ram_into_bank(addr, val),
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+
+    fn snapshot(&self) -> MbcState {
+        let select_mode = match self.select_mode {
+            Mbc1SelectMode::ROM => 0,
+            Mbc1SelectMode::RAM => 1,
+        };
+
+        MbcState {
+            ram: self.ram.clone(),
+            registers: vec![
+                self.rom_bank,
+                self.bank2,
+                self.enable_ram as u8,
+                select_mode,
+            ],
+        }
+    }
+
+    fn restore(&mut self, state: &MbcState) -> Result<()> {
+        if state.ram.len() != self.ram.len() || state.registers.len() != 4 {
+            return Err(GbError::InvalidState("mbc"));
+        }
+
+        self.ram.copy_from_slice(&state.ram);
+        self.rom_bank = state.registers[0];
+        self.bank2 = state.registers[1];
+        self.enable_ram = state.registers[2] != 0;
+        self.select_mode = match state.registers[3] {
+            0 => Mbc1SelectMode::ROM,
+            _ => Mbc1SelectMode::RAM,
+        };
+
+        Ok(())
+    }
+}
+
+// RTCのレジスタ (秒, 分, 時, 日の下位8bit, 日の上位1bit/停止/桁あふれ) と書き込み可能なビット
+const RTC_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+// RTCが1秒進むまでのTサイクル数
+const RTC_CYCLES_PER_SECOND: u32 = 4_194_304;
+
+pub struct Mbc3 {
+    rom: Rom,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    // 0x00-0x03: RAMバンク, 0x08-0x0C: RTCレジスタ
+    select: u8,
+    // RAMとRTCの両方の有効/無効
+    enable_ram: bool,
+    latch: u8,
+    rtc: [u8; 5],
+    latched_rtc: [u8; 5],
+    // 次の1秒までに経過したTサイクル数
+    rtc_cycles: u32,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Rom) -> Self {
+        let ram = vec![0; rom.ram_size];
+
+        Mbc3 {
+            rom,
+            ram,
+            rom_bank: 1,
+            select: 0,
+            enable_ram: false,
+            latch: 0xFF,
+            rtc: [0; 5],
+            latched_rtc: [0; 5],
+            rtc_cycles: 0,
+        }
+    }
+
+    // RTCを1秒進める。範囲外の値は桁上がりせずにビット幅で折り返す
+    fn advance_rtc_second(&mut self) {
+        self.rtc[0] = (self.rtc[0] + 1) & RTC_MASKS[0];
+        if self.rtc[0] != 60 {
+            return;
+        }
+        self.rtc[0] = 0;
+
+        self.rtc[1] = (self.rtc[1] + 1) & RTC_MASKS[1];
+        if self.rtc[1] != 60 {
+            return;
+        }
+        self.rtc[1] = 0;
+
+        self.rtc[2] = (self.rtc[2] + 1) & RTC_MASKS[2];
+        if self.rtc[2] != 24 {
+            return;
+        }
+        self.rtc[2] = 0;
+
+        let day = (((self.rtc[4] & 0x01) as u16) << 8 | self.rtc[3] as u16) + 1;
+
+        self.rtc[3] = day as u8;
+        self.rtc[4] = (self.rtc[4] & 0xFE) | ((day >> 8) as u8 & 0x01);
+
+        // 511日を超えると0日に戻り、桁あふれフラグが立つ
+        if day > 0x1FF {
+            self.rtc[4] |= 0x80;
+        }
+    }
+
+    fn read_rom_from_bank(&self, addr: u16) -> Result<u8> {
+        let bank = self.rom_bank as usize % self.rom.rom_banks();
+        let base_addr = bank * ROM_BANK_SIZE;
+        let index_addr = (addr - 0x4000) as usize;
+
+        Ok(self
+            .rom
+            .data
+            .get(base_addr + index_addr)
+            .copied()
+            .unwrap_or(OPEN_BUS))
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        let banks = self.rom.ram_banks();
+
+        if banks == 0 {
+            return None;
+        }
+
+        Some((self.select as usize % banks) * RAM_BANK_SIZE + (addr - 0xA000) as usize)
+    }
+
+    fn read_ram_or_rtc(&self, addr: u16) -> Result<u8> {
+        // 無効時はオープンバス
+        if !self.enable_ram {
+            return Ok(OPEN_BUS);
+        }
+
+        match self.select {
+            0x00..=0x03 => Ok(self
+                .ram_offset(addr)
+                .and_then(|offset| self.ram.get(offset))
+                .copied()
+                .unwrap_or(OPEN_BUS)),
+            // RTCはラッチした値を読む。未使用のビットは0
+            0x08..=0x0C => Ok(self.latched_rtc[(self.select - 0x08) as usize]),
+            _ => Ok(OPEN_BUS),
+        }
+    }
+
+    fn write_ram_or_rtc(&mut self, addr: u16, val: u8) -> Result<()> {
+        if !self.enable_ram {
+            return Ok(());
+        }
+
+        match self.select {
+            0x00..=0x03 => {
+                if let Some(byte) = self
+                    .ram_offset(addr)
+                    .and_then(|offset| self.ram.get_mut(offset))
+                {
+                    *byte = val;
                 }
-                Mbc1SelectMode::RAM => {
-                    let bank = val & 0b00000011;
+            }
+            0x08..=0x0C => {
+                let i = (self.select - 0x08) as usize;
 
-                    self.ram_bank = bank;
+                self.rtc[i] = val & RTC_MASKS[i];
+                self.latched_rtc[i] = self.rtc[i];
 
-                    Ok(())
+                // 秒を書き込むと1秒未満のカウンタもリセットされる
+                if i == 0 {
+                    self.rtc_cycles = 0;
                 }
-            },
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read(&self, addr: u16) -> Result<u8> {
+        match addr {
+            0x0000..=0x3FFF => Ok(self
+                .rom
+                .data
+                .get(addr as usize)
+                .copied()
+                .unwrap_or(OPEN_BUS)),
+            0x4000..=0x7FFF => self.read_rom_from_bank(addr),
+            0xA000..=0xBFFF => self.read_ram_or_rtc(addr),
+            _ => Err(GbError::UnmappedRead(addr)),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<()> {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.enable_ram = (val & 0x0F) == 0x0A;
+
+                Ok(())
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank = max(val & 0b01111111, 1);
+
+                Ok(())
+            }
+            0x4000..=0x5FFF => {
+                self.select = val;
+
+                Ok(())
+            }
             0x6000..=0x7FFF => {
-                self.select_mode = match val {
-                    0x01 => Mbc1SelectMode::RAM,
-                    _ => Mbc1SelectMode::ROM,
-                };
+                // 0から1を書き込むと、その時点のRTCをラッチする
+                if self.latch == 0x00 && val == 0x01 {
+                    self.latched_rtc = self.rtc;
+                }
+
+                self.latch = val;
 
                 Ok(())
             }
-            addr => self.write_ram_into_bank(addr, val),
+            0xA000..=0xBFFF => self.write_ram_or_rtc(addr, val),
+            _ => Err(GbError::UnmappedWrite(addr)),
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+
+    fn snapshot(&self) -> MbcState {
+        let mut registers = vec![
+            self.rom_bank,
+            self.select,
+            self.enable_ram as u8,
+            self.latch,
+        ];
+        registers.extend_from_slice(&self.rtc);
+        registers.extend_from_slice(&self.latched_rtc);
+        registers.extend_from_slice(&self.rtc_cycles.to_le_bytes());
+
+        MbcState {
+            ram: self.ram.clone(),
+            registers,
+        }
+    }
+
+    fn restore(&mut self, state: &MbcState) -> Result<()> {
+        if state.ram.len() != self.ram.len() || state.registers.len() != 18 {
+            return Err(GbError::InvalidState("mbc"));
+        }
+
+        self.ram.copy_from_slice(&state.ram);
+        self.rom_bank = state.registers[0];
+        self.select = state.registers[1];
+        self.enable_ram = state.registers[2] != 0;
+        self.latch = state.registers[3];
+        self.rtc.copy_from_slice(&state.registers[4..9]);
+        self.latched_rtc.copy_from_slice(&state.registers[9..14]);
+        self.rtc_cycles = u32::from_le_bytes([
+            state.registers[14],
+            state.registers[15],
+            state.registers[16],
+            state.registers[17],
+        ]);
+
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        // 停止ビットが立っている間は進まない
+        if self.rtc[4] & 0x40 != 0 {
+            return;
+        }
+
+        self.rtc_cycles += 1;
+
+        if self.rtc_cycles >= RTC_CYCLES_PER_SECOND {
+            self.rtc_cycles = 0;
+            self.advance_rtc_second();
         }
     }
 }
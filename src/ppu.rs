@@ -1,12 +1,23 @@
-use anyhow::Result;
+use crate::error::{GbError, Result};
+use alloc::vec::Vec;
 use bitfield::bitfield;
 use bitmatch::bitmatch;
-use image::{ImageBuffer, Rgba};
-
-const VISIBLE_WIDTH: usize = 160;
-const VISIBLE_HEIGHT: usize = 144;
+use serde::{Deserialize, Serialize};
+
+pub const VISIBLE_WIDTH: usize = 160;
+pub const VISIBLE_HEIGHT: usize = 144;
+pub const FRAME_SIZE: usize = VISIBLE_WIDTH * VISIBLE_HEIGHT * 4;
+pub const DEFAULT_DMG_PALETTE: [[u8; 4]; 4] = [
+    [0xD8, 0xF7, 0xD7, 0xFF],
+    [0x6C, 0xA6, 0x6B, 0xFF],
+    [0x20, 0x59, 0x4A, 0xFF],
+    [0x00, 0x14, 0x1B, 0xFF],
+];
 const WIDTH: usize = 256;
 const HEIGHT: usize = 256;
+// デバッグ用の256x256のBG/ウィンドウ全体の画像 (RGBA8)
+pub const FULL_BG_SIZE: usize = WIDTH * HEIGHT * 4;
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
 
 bitfield! {
     struct LcdControl(u8);
@@ -72,6 +83,26 @@ struct Oam {
     x_pos: u8,
     tile_num: u8,
     sprite_flag: SpriteFlags,
+    // OAMスキャン時点のLCDCで決まった高さ。描画中にLCDCが変わってもこちらを使う
+    height: u8,
+}
+
+impl From<Oam> for [u8; 4] {
+    fn from(oam: Oam) -> Self {
+        [oam.y_pos, oam.x_pos, oam.tile_num, oam.sprite_flag.0]
+    }
+}
+
+impl From<&[u8]> for Oam {
+    fn from(bytes: &[u8]) -> Self {
+        Self {
+            y_pos: bytes[0],
+            x_pos: bytes[1],
+            tile_num: bytes[2],
+            sprite_flag: SpriteFlags(bytes[3]),
+            height: 8,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -82,6 +113,57 @@ enum Mode {
     Drawing = 3,
 }
 
+impl From<u8> for Mode {
+    fn from(val: u8) -> Self {
+        match val & 0b11 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::Drawing,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ColorCorrection {
+    #[default]
+    None,
+    // GBCの液晶の発色に近づける (RGB555の各成分を混色して彩度を落とす)
+    GbcLcd,
+    Grayscale,
+}
+
+impl ColorCorrection {
+    pub fn apply(self, pixel: [u8; 4]) -> [u8; 4] {
+        let [r, g, b, a] = pixel;
+
+        match self {
+            ColorCorrection::None => pixel,
+            ColorCorrection::GbcLcd => {
+                let (r, g, b) = ((r >> 3) as u32, (g >> 3) as u32, (b >> 3) as u32);
+
+                let cr = (r * 26 + g * 4 + b * 2).min(960) >> 2;
+                let cg = (g * 24 + b * 8).min(960) >> 2;
+                let cb = (r * 6 + g * 4 + b * 22).min(960) >> 2;
+
+                [cr as u8, cg as u8, cb as u8, a]
+            }
+            ColorCorrection::Grayscale => {
+                let y = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+
+                [y as u8, y as u8, y as u8, a]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
 type ColorIndex = u8;
 
 #[derive(Debug, Copy, Clone)]
@@ -89,6 +171,10 @@ struct OamColor {
     index: ColorIndex,
     color: u8,
     blend: bool,
+    // 小さいほど優先される
+    priority: u16,
+    // OBP1を使うかどうか
+    obp1: bool,
 }
 
 impl Default for OamColor {
@@ -97,12 +183,19 @@ impl Default for OamColor {
             index: 0,
             blend: false,
             color: 0,
+            priority: u16::MAX,
+            obp1: false,
         }
     }
 }
 
 impl OamColor {
-    fn from_indexes(indexes: [ColorIndex; 8], blend: bool, palette: &Palette) -> [OamColor; 8] {
+    fn from_indexes(
+        indexes: [ColorIndex; 8],
+        blend: bool,
+        palette: &Palette,
+        obp1: bool,
+    ) -> [OamColor; 8] {
         let mut colors: [OamColor; 8] = [Default::default(); 8];
 
         for (j, &index) in indexes.iter().enumerate() {
@@ -110,6 +203,8 @@ impl OamColor {
                 index,
                 blend,
                 color: palette.0[index as usize],
+                priority: u16::MAX,
+                obp1,
             }
         }
 
@@ -117,6 +212,40 @@ impl OamColor {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PpuState {
+    pub vram: Vec<u8>,
+    pub oam: Vec<u8>,
+    pub mode: u8,
+    pub prev_mode: u8,
+    pub lcd_control: u8,
+    pub lcd_status: u8,
+    pub window_x: u8,
+    pub window_y: u8,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub cycles: u16,
+    pub lines: u8,
+    pub lines_compare: u8,
+    pub bg_palette: u8,
+    pub object_palette_0: u8,
+    pub object_palette_1: u8,
+    pub int_v_blank: bool,
+    pub int_lcd_stat: bool,
+    pub frame_ready: bool,
+    pub skip_frame: bool,
+    pub line_ready: bool,
+    pub x: u8,
+    pub y: u8,
+    pub buffer: Vec<([u8; 4], u8)>,
+    pub bg_line: Vec<u8>,
+    pub bg_color_line: Vec<u8>,
+    pub oam_line: Vec<(u8, u8, bool, u16, bool)>,
+    pub cur_bg: [u8; 8],
+    pub drawing_window: bool,
+    pub object_priority_mode: u8,
+}
+
 pub struct Ppu {
     vram: [u8; 8 * 1024],
 
@@ -141,19 +270,35 @@ pub struct Ppu {
 
     pub int_v_blank: bool,
     pub int_lcd_stat: bool,
+    pub frame_ready: bool,
+    skip_frame: bool,
+    // HBlankで描画を終え、まだ画面バッファに書き出していないラインがあるかどうか
+    line_ready: bool,
+
+    // デバッグ用。LCDCに関わらず表示だけを無効にする
+    pub force_bg_off: bool,
+    pub force_window_off: bool,
+    pub force_sprites_off: bool,
 
     x: u8,
     y: u8,
 
-    oam: [Oam; 0xA0],
+    oam: [u8; 0xA0],
     buffer: Vec<Oam>,
 
     bg_line: [ColorIndex; WIDTH],
+    // 描画したドット時点のBGPを適用した色
+    bg_color_line: [u8; WIDTH],
     oam_line: [OamColor; WIDTH],
     cur_bg: [ColorIndex; 8],
     drawing_window: bool,
 
-    pixels: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    // OPRI: 0=OAMの順序で優先 (CGB), 1=X座標で優先 (DMG)
+    object_priority_mode: u8,
+
+    color_correction: ColorCorrection,
+    // BG/ウィンドウ、OBP0、OBP1それぞれの4階調のRGBA
+    dmg_palettes: [[[u8; 4]; 4]; 3],
 }
 
 impl Ppu {
@@ -162,7 +307,8 @@ impl Ppu {
             vram: [0; 8 * 1024],
             mode: Mode::VBlank,
             prev_mode: Mode::VBlank,
-            lcd_control: LcdControl(0),
+            // ブートROMの終了時と同じくLCDが有効な状態から始める
+            lcd_control: LcdControl(0x91),
             lcd_status: LcdStatus(0),
             window_x: 0,
             window_y: 0,
@@ -178,24 +324,34 @@ impl Ppu {
             y: 0,
             int_v_blank: false,
             int_lcd_stat: false,
-            oam: [Oam::default(); 0xA0],
+            frame_ready: false,
+            skip_frame: false,
+            line_ready: false,
+            force_bg_off: false,
+            force_window_off: false,
+            force_sprites_off: false,
+            oam: [0; 0xA0],
             bg_line: [0; WIDTH],
+            bg_color_line: [0; WIDTH],
             oam_line: [Default::default(); WIDTH],
             cur_bg: [0; 8],
             drawing_window: false,
             buffer: Vec::new(),
-            pixels: ImageBuffer::new(VISIBLE_WIDTH as u32, VISIBLE_HEIGHT as u32),
+            object_priority_mode: 1,
+            color_correction: Default::default(),
+            dmg_palettes: [DEFAULT_DMG_PALETTE; 3],
         }
     }
 
-    fn color_to_pixel(&self, color: u8) -> Rgba<u8> {
-        match color {
-            0 => Rgba([0xD8, 0xF7, 0xD7, 0xFF]),
-            1 => Rgba([0x6C, 0xA6, 0x6B, 0xFF]),
-            2 => Rgba([0x20, 0x59, 0x4A, 0xFF]),
-            3 => Rgba([0x00, 0x14, 0x1B, 0xFF]),
-            _ => Rgba([0xFF, 0xFF, 0xFF, 0xFF]),
-        }
+    fn color_to_pixel(&self, layer: usize, color: u8) -> [u8; 4] {
+        self.dmg_palettes[layer]
+            .get(color as usize)
+            .copied()
+            .unwrap_or([0xFF, 0xFF, 0xFF, 0xFF])
+    }
+
+    fn vram_at(&self, index: u16) -> u8 {
+        self.vram.get(index as usize).copied().unwrap_or(0xFF)
     }
 
     #[bitmatch]
@@ -215,16 +371,17 @@ impl Ppu {
 
         let addr = base_addr.wrapping_add(index_addr);
 
-        let bit = self.vram[addr as usize];
-        let color = self.vram[(addr + 1) as usize];
+        // 偶数アドレスが下位ビット、奇数アドレスが上位ビットの面で、bit7が左端のピクセル
+        let low = self.vram_at(addr);
+        let high = self.vram_at(addr.wrapping_add(1));
 
         let mut indexes = [0; 8];
 
         #[bitmatch]
-        let "acegikmo" = bit;
+        let "acegikmo" = high;
 
         #[bitmatch]
-        let "bdfhjlnp" = color;
+        let "bdfhjlnp" = low;
 
         #[bitmatch]
         let "aabbccddeeffgghh" = bitpack!("abcdefghijklmnop");
@@ -247,23 +404,22 @@ impl Ppu {
 
         let addr = base_addr.wrapping_add(index_addr);
 
-        let tile_num = self.vram[addr as usize];
+        let tile_num = self.vram_at(addr);
 
         self.tile_to_indexes(tile_num, row, !self.lcd_control.tile_data_select())
     }
 
     fn oam_to_colors(&self, oam: &Oam) -> [OamColor; 8] {
         let mut row = self.y + 16 - oam.y_pos;
-        let mut tile = oam.tile_num;
+        // 8x16では下位ビットを無視して偶数、奇数の2タイルを上下に並べる
+        let mut tile = if oam.height == 16 {
+            oam.tile_num & 0xFE
+        } else {
+            oam.tile_num
+        };
 
         if oam.sprite_flag.y_flip() {
-            let limit = if self.lcd_control.sprite_size() {
-                16
-            } else {
-                8
-            };
-
-            row = limit - row - 1;
+            row = oam.height - row - 1;
         }
 
         if row >= 8 {
@@ -271,7 +427,8 @@ impl Ppu {
             tile += 1;
         }
 
-        let palette = if oam.sprite_flag.palette_num() {
+        let obp1 = oam.sprite_flag.palette_num();
+        let palette = if obp1 {
             &self.object_palette_1
         } else {
             &self.object_palette_0
@@ -280,7 +437,7 @@ impl Ppu {
         let blend = oam.sprite_flag.priority();
 
         let mut colors =
-            OamColor::from_indexes(self.tile_to_indexes(tile, row, false), blend, palette);
+            OamColor::from_indexes(self.tile_to_indexes(tile, row, false), blend, palette, obp1);
 
         if oam.sprite_flag.x_flip() {
             colors.reverse();
@@ -296,11 +453,18 @@ impl Ppu {
             8
         };
 
-        let oam = self.oam[i];
+        let oam = Oam {
+            height: size,
+            ..Oam::from(&self.oam[(i * 4)..(i * 4 + 4)])
+        };
         let cur_y = self.lines as u16 + 16;
         let target_y = oam.y_pos as u16;
 
-        if oam.x_pos > 8 && cur_y < target_y + size && target_y <= cur_y && self.buffer.len() < 10 {
+        if oam.x_pos > 8
+            && cur_y < target_y + size as u16
+            && target_y <= cur_y
+            && self.buffer.len() < 10
+        {
             self.buffer.push(oam);
         }
     }
@@ -349,34 +513,86 @@ impl Ppu {
         self.bg_line[self.x as usize] = self.cur_bg[col as usize];
     }
 
+    fn sprite_priority(&self, i: usize, oam: &Oam) -> u16 {
+        // bufferはOAMの順序で並んでいるため、その位置をOAMの順序として使える
+        if self.object_priority_mode & 0x01 == 0 {
+            i as u16
+        } else {
+            ((oam.x_pos as u16) << 8) | i as u16
+        }
+    }
+
     fn draw_sprite(&mut self) {
-        for oam in self.buffer.iter() {
+        for (i, oam) in self.buffer.iter().enumerate() {
             if oam.x_pos == self.x + 8 {
                 let x = self.x as usize;
 
+                let priority = self.sprite_priority(i, oam);
                 let colors = self.oam_to_colors(oam);
 
-                self.oam_line[x..(x + 8)].copy_from_slice(&colors[..]);
+                for (cur, &color) in self.oam_line[x..(x + 8)].iter_mut().zip(colors.iter()) {
+                    if color.index != 0 && (cur.index == 0 || priority < cur.priority) {
+                        *cur = OamColor { priority, ..color };
+                    }
+                }
             }
         }
     }
 
-    fn put_pixels(&mut self, x: u8) {
+    fn put_pixels(&self, x: u8, frame: &mut [u8]) {
         let x = x as usize;
-        let index = self.bg_line[x] as usize;
-        let mut color = self.bg_palette.0[index];
+        let index = self.bg_line[x];
+        let mut color = self.bg_color_line[x];
+
+        let mut layer = 0;
 
         let oam = self.oam_line[x];
 
         if (!oam.blend || index == 0) && oam.index != 0 {
             color = oam.color;
+            layer = 1 + oam.obp1 as usize;
         }
 
-        self.pixels
-            .put_pixel(x as u32, self.y as u32, self.color_to_pixel(color));
+        let offset = (self.y as usize * VISIBLE_WIDTH + x) * 4;
+
+        let pixel = self
+            .color_correction
+            .apply(self.color_to_pixel(layer, color));
+
+        frame[offset..(offset + 4)].copy_from_slice(&pixel);
+    }
+
+    /// `frame` はフロントエンドが所有する RGBA8 の画面バッファ。
+    /// 160x144 の行優先で `FRAME_SIZE` バイトちょうどでなければならない。
+    /// HBlank に入ったラインがあればその 1 ライン分だけを直接書き込むため、
+    /// `tick` の後に毎回呼べば VBlank 時点で 1 フレーム分が揃う。
+    pub fn put_line(&mut self, frame: &mut [u8]) -> Result<()> {
+        if frame.len() != FRAME_SIZE {
+            return Err(GbError::InvalidFrameSize {
+                expected: FRAME_SIZE,
+                actual: frame.len(),
+            });
+        }
+
+        if !self.line_ready {
+            return Ok(());
+        }
+
+        self.line_ready = false;
+
+        for x in 0..VISIBLE_WIDTH as u8 {
+            self.put_pixels(x, frame);
+        }
+
+        Ok(())
     }
 
     pub fn tick(&mut self) -> Result<()> {
+        // LCDが無効の間はLYを0、モードを0のまま止め、割り込みも起こさない
+        if !self.lcd_control.lcd_display_enable() {
+            return Ok(());
+        }
+
         self.cycles += 1;
 
         if self.cycles >= 456 {
@@ -430,34 +646,36 @@ impl Ppu {
                     self.lcd_status.set_ppu_mode(0b11);
                 }
                 if self.lcd_control.bg_win_enable() {
-                    if self.lcd_control.window_display_enable() {
+                    if self.lcd_control.window_display_enable() && !self.force_window_off {
                         self.draw_window();
                     }
 
-                    self.draw_bg();
+                    if !self.force_bg_off {
+                        self.draw_bg();
+                    }
                 }
 
-                if self.lcd_control.sprite_enable() {
+                if self.lcd_control.sprite_enable() && !self.force_sprites_off {
                     self.draw_sprite();
                 }
-            }
-            Mode::HBlank => {
-                if first {
-                    self.lcd_status.set_ppu_mode(0b00);
 
-                    self.lcd_status
-                        .set_coincidence_flag(self.lines == self.lines_compare);
+                // SCX/SCY/BGPはドット毎に参照し、ライン途中の書き換えをそのドットから反映する
+                let x = self.x as usize;
+                self.bg_color_line[x] = self.bg_palette.0[self.bg_line[x] as usize];
+            }
+            Mode::HBlank if first => {
+                self.lcd_status.set_ppu_mode(0b00);
 
-                    self.int_lcd_stat |= self.lcd_status.mode_0_stat_int_enable();
-                    self.int_lcd_stat |= self.lcd_status.coincidence_flag()
-                        && self.lcd_status.lyc_ly_stat_int_enable();
+                self.lcd_status
+                    .set_coincidence_flag(self.lines == self.lines_compare);
 
-                    self.drawing_window = false;
-                }
+                self.int_lcd_stat |= self.lcd_status.mode_0_stat_int_enable();
+                self.int_lcd_stat |=
+                    self.lcd_status.coincidence_flag() && self.lcd_status.lyc_ly_stat_int_enable();
 
-                if self.cycles < 400 {
-                    self.put_pixels((self.cycles - 240) as u8);
-                }
+                self.drawing_window = false;
+                // 最初のフレームは表示しない
+                self.line_ready = !self.skip_frame;
             }
             Mode::OamScan => {
                 if first {
@@ -475,6 +693,9 @@ impl Ppu {
                     self.lcd_status.set_ppu_mode(0b01);
                     self.int_v_blank = true;
                     self.int_lcd_stat |= self.lcd_status.mode_1_stat_int_enable();
+
+                    self.frame_ready = !self.skip_frame;
+                    self.skip_frame = false;
                 }
             }
             _ => {}
@@ -486,60 +707,344 @@ impl Ppu {
     }
 
     pub fn read(&self, addr: u16) -> Result<u8> {
-        Ok(self.vram[(addr - 0x8000) as usize])
+        addr.checked_sub(0x8000)
+            .and_then(|i| self.vram.get(i as usize))
+            .copied()
+            .ok_or(GbError::UnmappedRead(addr))
     }
 
     pub fn write(&mut self, addr: u16, val: u8) -> Result<()> {
         // println!("PPU WRITE: {:#02X}={:#02X}", addr, val);
-        self.vram[(addr - 0x8000) as usize] = val;
+        let byte = addr
+            .checked_sub(0x8000)
+            .and_then(|i| self.vram.get_mut(i as usize))
+            .ok_or(GbError::UnmappedWrite(addr))?;
+
+        *byte = val;
         Ok(())
     }
 
+    pub fn fill_vram(&mut self, bytes: &mut dyn Iterator<Item = u8>) {
+        for (dst, src) in self.vram.iter_mut().zip(bytes) {
+            *dst = src;
+        }
+    }
+
     pub fn read_oam(&self, addr: u16) -> Result<u8> {
-        let index_addr = addr - 0xFE00;
-        let index = index_addr / 4;
-        let offset = index_addr % 4;
-        let oam = self.oam[index as usize];
+        addr.checked_sub(0xFE00)
+            .and_then(|i| self.oam.get(i as usize))
+            .copied()
+            .ok_or(GbError::UnmappedRead(addr))
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_control.lcd_display_enable() || matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    // DMGのOAM bug (書き込み側)。OAMスキャン中に0xFE00-0xFEFFを指すアドレスが
+    // インクリメント/デクリメントされると、スキャン中の行が前の行の内容で壊れる
+    pub fn corrupt_oam_write(&mut self) {
+        self.corrupt_oam(|a, b, c| ((a ^ c) & (b ^ c)) ^ c);
+    }
 
-        match offset {
-            0 => Ok(oam.y_pos),
-            1 => Ok(oam.x_pos),
-            2 => Ok(oam.tile_num),
-            3 => Ok(oam.sprite_flag.0),
-            _ => unreachable!(),
+    // DMGのOAM bug (読み込み側)。壊れ方は書き込みと同じで、先頭のワードの式だけが異なる
+    pub fn corrupt_oam_read(&mut self) {
+        self.corrupt_oam(|a, b, c| b | (a & c));
+    }
+
+    fn corrupt_oam(&mut self, first: impl Fn(u16, u16, u16) -> u16) {
+        if !self.lcd_control.lcd_display_enable() || self.mode != Mode::OamScan {
+            return;
         }
+
+        // 1行は8バイト(オブジェクト2つ)で、4ドットごとに次の行に進む
+        let row = (self.cycles / 4) as usize;
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let word = |oam: &[u8; 0xA0], i: usize| u16::from_le_bytes([oam[i], oam[i + 1]]);
+
+        let cur = row * 8;
+        let prev = cur - 8;
+        let a = word(&self.oam, cur);
+        let b = word(&self.oam, prev);
+        let c = word(&self.oam, prev + 4);
+
+        self.oam[cur..cur + 2].copy_from_slice(&first(a, b, c).to_le_bytes());
+        self.oam.copy_within(prev + 2..prev + 8, cur + 2);
     }
 
     pub fn write_oam(&mut self, addr: u16, val: u8) -> Result<()> {
-        let index_addr = addr - 0xFE00;
-        let index = (index_addr / 4) as usize;
-        let offset = index_addr % 4;
+        let byte = addr
+            .checked_sub(0xFE00)
+            .and_then(|i| self.oam.get_mut(i as usize))
+            .ok_or(GbError::UnmappedWrite(addr))?;
 
-        match offset {
-            0 => {
-                self.oam[index].y_pos = val;
-            }
-            1 => {
-                self.oam[index].x_pos = val;
-            }
-            2 => {
-                self.oam[index].tile_num = val;
-            }
-            3 => {
-                self.oam[index].sprite_flag.0 = val;
+        *byte = val;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> PpuState {
+        PpuState {
+            vram: self.vram.to_vec(),
+            oam: self.oam.to_vec(),
+            mode: self.mode as u8,
+            prev_mode: self.prev_mode as u8,
+            lcd_control: self.lcd_control.0,
+            lcd_status: self.lcd_status.0,
+            window_x: self.window_x,
+            window_y: self.window_y,
+            scroll_x: self.scroll_x,
+            scroll_y: self.scroll_y,
+            cycles: self.cycles,
+            lines: self.lines,
+            lines_compare: self.lines_compare,
+            bg_palette: self.bg_palette.into(),
+            object_palette_0: self.object_palette_0.into(),
+            object_palette_1: self.object_palette_1.into(),
+            int_v_blank: self.int_v_blank,
+            int_lcd_stat: self.int_lcd_stat,
+            frame_ready: self.frame_ready,
+            skip_frame: self.skip_frame,
+            line_ready: self.line_ready,
+            x: self.x,
+            y: self.y,
+            buffer: self
+                .buffer
+                .iter()
+                .map(|&oam| (oam.into(), oam.height))
+                .collect(),
+            bg_line: self.bg_line.to_vec(),
+            bg_color_line: self.bg_color_line.to_vec(),
+            oam_line: self
+                .oam_line
+                .iter()
+                .map(|c| (c.index, c.color, c.blend, c.priority, c.obp1))
+                .collect(),
+            cur_bg: self.cur_bg,
+            drawing_window: self.drawing_window,
+            object_priority_mode: self.object_priority_mode,
+        }
+    }
+
+    pub fn restore(&mut self, state: &PpuState) -> Result<()> {
+        if state.vram.len() != self.vram.len()
+            || state.oam.len() != self.oam.len()
+            || state.bg_line.len() != WIDTH
+            || state.bg_color_line.len() != WIDTH
+            || state.oam_line.len() != WIDTH
+            || state
+                .buffer
+                .iter()
+                .any(|&(_, height)| height != 8 && height != 16)
+        {
+            return Err(GbError::InvalidState("ppu"));
+        }
+
+        self.vram.copy_from_slice(&state.vram);
+        self.oam.copy_from_slice(&state.oam);
+        self.mode = Mode::from(state.mode);
+        self.prev_mode = Mode::from(state.prev_mode);
+        self.lcd_control = LcdControl(state.lcd_control);
+        self.lcd_status = LcdStatus(state.lcd_status);
+        self.window_x = state.window_x;
+        self.window_y = state.window_y;
+        self.scroll_x = state.scroll_x;
+        self.scroll_y = state.scroll_y;
+        self.cycles = state.cycles;
+        self.lines = state.lines;
+        self.lines_compare = state.lines_compare;
+        self.bg_palette = Palette::from(state.bg_palette);
+        self.object_palette_0 = Palette::from(state.object_palette_0);
+        self.object_palette_1 = Palette::from(state.object_palette_1);
+        self.int_v_blank = state.int_v_blank;
+        self.int_lcd_stat = state.int_lcd_stat;
+        self.frame_ready = state.frame_ready;
+        self.skip_frame = state.skip_frame;
+        self.line_ready = state.line_ready;
+        self.x = state.x;
+        self.y = state.y;
+        self.buffer = state
+            .buffer
+            .iter()
+            .map(|(b, height)| Oam {
+                height: *height,
+                ..Oam::from(&b[..])
+            })
+            .collect();
+        self.bg_line.copy_from_slice(&state.bg_line);
+        self.bg_color_line.copy_from_slice(&state.bg_color_line);
+
+        for (c, &(index, color, blend, priority, obp1)) in
+            self.oam_line.iter_mut().zip(state.oam_line.iter())
+        {
+            *c = OamColor {
+                index,
+                color,
+                blend,
+                priority,
+                obp1,
+            };
+        }
+
+        self.cur_bg = state.cur_bg;
+        self.drawing_window = state.drawing_window;
+        self.object_priority_mode = state.object_priority_mode;
+
+        Ok(())
+    }
+
+    // 4階調それぞれのRGBA。BGとスプライトで共通
+    pub fn set_dmg_palette(&mut self, palette: [[u8; 4]; 4]) {
+        self.dmg_palettes = [palette; 3];
+    }
+
+    // BG/ウィンドウ、OBP0、OBP1で別々のパレットを使う
+    pub fn set_dmg_palettes(&mut self, bg: [[u8; 4]; 4], obj0: [[u8; 4]; 4], obj1: [[u8; 4]; 4]) {
+        self.dmg_palettes = [bg, obj0, obj1];
+    }
+
+    pub fn dmg_palettes(&self) -> [[[u8; 4]; 4]; 3] {
+        self.dmg_palettes
+    }
+
+    // 切り替え後に表示されるかどうかを返す
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        let off = match layer {
+            Layer::Background => &mut self.force_bg_off,
+            Layer::Window => &mut self.force_window_off,
+            Layer::Sprites => &mut self.force_sprites_off,
+        };
+
+        *off = !*off;
+
+        !*off
+    }
+
+    pub fn set_color_correction(&mut self, mode: ColorCorrection) {
+        self.color_correction = mode;
+    }
+
+    /// BGのタイルマップ全体 (256x256) を描画し、SCX/SCYの位置に表示範囲の枠を重ねる。
+    pub fn render_full_bg(&self, out: &mut [u8]) -> Result<()> {
+        self.render_full_map(out, self.lcd_control.bg_tile_map_select())?;
+
+        // 表示範囲は256x256の端で折り返す
+        for i in 0..VISIBLE_WIDTH {
+            let x = self.scroll_x.wrapping_add(i as u8);
+            self.put_full_pixel(out, x, self.scroll_y, VIEWPORT_COLOR);
+            self.put_full_pixel(
+                out,
+                x,
+                self.scroll_y.wrapping_add(VISIBLE_HEIGHT as u8 - 1),
+                VIEWPORT_COLOR,
+            );
+        }
+
+        for i in 0..VISIBLE_HEIGHT {
+            let y = self.scroll_y.wrapping_add(i as u8);
+            self.put_full_pixel(out, self.scroll_x, y, VIEWPORT_COLOR);
+            self.put_full_pixel(
+                out,
+                self.scroll_x.wrapping_add(VISIBLE_WIDTH as u8 - 1),
+                y,
+                VIEWPORT_COLOR,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// ウィンドウのタイルマップ全体 (256x256) を描画する。ウィンドウは常に左上から表示される。
+    pub fn render_full_window(&self, out: &mut [u8]) -> Result<()> {
+        self.render_full_map(out, self.lcd_control.window_tile_map_select())
+    }
+
+    fn render_full_map(&self, out: &mut [u8], high: bool) -> Result<()> {
+        if out.len() != FULL_BG_SIZE {
+            return Err(GbError::InvalidFrameSize {
+                expected: FULL_BG_SIZE,
+                actual: out.len(),
+            });
+        }
+
+        for y in 0..HEIGHT as u16 {
+            let y = y as u8;
+
+            for tile_x in 0..(WIDTH / 8) as u8 {
+                let indexes = self.tile_map_to_colors(tile_x, y / 8, y % 8, high);
+
+                for (col, &index) in indexes.iter().enumerate() {
+                    let color = self.bg_palette.0[index as usize];
+                    let pixel = self.color_correction.apply(self.color_to_pixel(0, color));
+
+                    self.put_full_pixel(out, tile_x * 8 + col as u8, y, pixel);
+                }
             }
-            _ => unreachable!(),
         }
 
         Ok(())
     }
 
+    fn put_full_pixel(&self, out: &mut [u8], x: u8, y: u8, pixel: [u8; 4]) {
+        let offset = (y as usize * WIDTH + x as usize) * 4;
+
+        out[offset..(offset + 4)].copy_from_slice(&pixel);
+    }
+
+    pub fn vram(&self) -> &[u8] {
+        &self.vram[..]
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam[..]
+    }
+
     pub fn read_lcd_control(&self) -> Result<u8> {
         Ok(self.lcd_control.0)
     }
 
     pub fn write_lcd_control(&mut self, val: u8) -> Result<()> {
+        let prev_enable = self.lcd_control.lcd_display_enable();
+
         self.lcd_control = LcdControl(val);
+
+        // LCDを無効にするとLYは0、モードは0 (HBlank) になる
+        if prev_enable && !self.lcd_control.lcd_display_enable() {
+            self.cycles = 0;
+            self.lines = 0;
+            self.mode = Mode::HBlank;
+            self.prev_mode = Mode::HBlank;
+            self.lcd_status.set_ppu_mode(0b00);
+            self.line_ready = false;
+        }
+
+        // LCDを有効にした直後はフレームの先頭から描画を始め、最初のフレームは表示しない
+        if !prev_enable && self.lcd_control.lcd_display_enable() {
+            self.cycles = 0;
+            self.lines = 0;
+            self.x = 0;
+            self.y = 0;
+            self.mode = Mode::OamScan;
+            self.prev_mode = Mode::VBlank;
+            self.buffer.clear();
+            self.bg_line = [0; WIDTH];
+            self.oam_line = [Default::default(); WIDTH];
+            self.drawing_window = false;
+            self.skip_frame = true;
+            self.line_ready = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_object_priority_mode(&self) -> Result<u8> {
+        Ok(0xFE | self.object_priority_mode)
+    }
+
+    pub fn write_object_priority_mode(&mut self, val: u8) -> Result<()> {
+        self.object_priority_mode = val & 0x01;
         Ok(())
     }
 
@@ -627,9 +1132,4 @@ impl Ppu {
         self.object_palette_1 = Palette::from(val);
         Ok(())
     }
-
-    pub fn render(&mut self, frame: &mut [u8]) -> Result<()> {
-        frame.copy_from_slice(&self.pixels.clone().into_raw());
-        Ok(())
-    }
 }
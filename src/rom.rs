@@ -1,10 +1,20 @@
+use crate::error::{GbError, Result};
 use crate::utils::*;
-use anyhow::{bail, Context, Result};
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cmp::max;
+use core::fmt;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
-use std::fmt;
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::{BufRead, Seek, SeekFrom};
+
+const NINTENDO_LOGO: [u8; 0x0030] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
 
 #[derive(FromPrimitive, Debug)]
 pub enum MbcType {
@@ -19,6 +29,8 @@ pub enum MbcType {
     Mmm01 = 0x0b,
     Mmm01Ram = 0x0c,
     Mmm01RamBattery = 0x0d,
+    Mbc3TimerBattery = 0x0f,
+    Mbc3TimerRamBattery = 0x10,
     Mbc3 = 0x11,
     Mbc3Ram = 0x12,
     Mbc3RamBattery = 0x13,
@@ -81,6 +93,20 @@ impl Default for Rom {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct RomReport {
+    pub header_checksum_ok: bool,
+    pub global_checksum_ok: bool,
+    pub size_matches: bool,
+    pub logo_matches: bool,
+}
+
+impl RomReport {
+    pub fn is_ok(&self) -> bool {
+        self.header_checksum_ok && self.global_checksum_ok && self.size_matches && self.logo_matches
+    }
+}
+
 impl fmt::Debug for Rom {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Rom")
@@ -102,148 +128,210 @@ impl fmt::Debug for Rom {
     }
 }
 
+pub const ROM_BANK_SIZE: usize = 16 * 1024;
+pub const RAM_BANK_SIZE: usize = 8 * 1024;
+
 impl Rom {
-    pub fn new(reader: &mut BufReader<File>) -> Result<Rom> {
+    /// 16KiBのROMバンクの数。ヘッダのROMサイズが不明な場合は実際のデータの長さから求める
+    pub fn rom_banks(&self) -> usize {
+        let size = if self.rom_size > 0 {
+            self.rom_size
+        } else {
+            self.data.len()
+        };
+
+        max(size.div_ceil(ROM_BANK_SIZE), 2)
+    }
+
+    /// 8KiBのRAMバンクの数。2KiBのRAMは部分的な1バンクとして数える
+    pub fn ram_banks(&self) -> usize {
+        self.ram_size.div_ceil(RAM_BANK_SIZE)
+    }
+
+    pub fn verify(&self) -> RomReport {
+        let header_checksum_ok = self.data.len() > 0x014D
+            && self.data[0x0134..=0x014C]
+                .iter()
+                .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+                == self.header_checksum;
+
+        // 014E-014F を除いた全バイトの和 (ビッグエンディアン)
+        let global_checksum = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+
+        RomReport {
+            header_checksum_ok,
+            global_checksum_ok: global_checksum == u16::from_be_bytes(self.global_checksum),
+            size_matches: self.rom_size == self.data.len(),
+            logo_matches: self.logo == NINTENDO_LOGO,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new<R: BufRead + Seek>(reader: &mut R) -> Result<Rom> {
+        Self::from_bytes(read_all(reader)?)
+    }
+
+    /// ヘッダチェックサムとROMサイズを検証せずに読み込む。
+    /// ブートROMを使う場合はロゴやチェックサムの検証 (と失敗時の停止) をブートROM自身に任せる。
+    #[cfg(feature = "std")]
+    pub fn new_unchecked<R: BufRead + Seek>(reader: &mut R) -> Result<Rom> {
+        Self::from_bytes_unchecked(read_all(reader)?)
+    }
+
+    /// ファイルを経由せず、メモリ上のROMイメージから読み込む (no_std環境向け)
+    pub fn from_bytes(data: Vec<u8>) -> Result<Rom> {
+        Self::load(data, true)
+    }
+
+    pub fn from_bytes_unchecked(data: Vec<u8>) -> Result<Rom> {
+        Self::load(data, false)
+    }
+
+    fn load(data: Vec<u8>, strict: bool) -> Result<Rom> {
         let mut rom = Rom::default();
+        let mut header = HeaderReader {
+            data: &data,
+            pos: 0x0100,
+        };
 
         // @see https://gbdev.io/pandocs/#the-cartridge-header
 
-        reader.seek(SeekFrom::Start(0x0100))?;
-
         // 0100-0103 - Entry Point
-        reader.read_exact(&mut rom.entry_point[..])?;
+        header.read_exact(&mut rom.entry_point[..], "the Entry Point")?;
 
         // 0104-0133 - Nintendo Logo
-        reader.read_exact(&mut rom.logo[..])?;
+        header.read_exact(&mut rom.logo[..], "the Nintendo Logo")?;
 
         // 0134-0143 - Title
         // NOTE: Manufacturer Code, CGB Flagを含む
-        reader.read_exact(&mut rom.title[..])?;
+        header.read_exact(&mut rom.title[..], "the Title")?;
 
         // 0144-0145 - New Licensee Code
-        reader.read_exact(&mut rom.new_licensee_code[..])?;
+        header.read_exact(&mut rom.new_licensee_code[..], "the New Licensee Code")?;
 
         // 0146 - SGB Flag
-        rom.sgb_flag = match reader.take(1).bytes().next() {
-            Some(Ok(0x00)) => false,
-            Some(Ok(0x03)) => true,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown SGB Flag {:#X}", unknown);
+        rom.sgb_flag = match header.byte("the SGB Flag")? {
+            0x00 => false,
+            0x03 => true,
+            unknown => {
+                log::warn!("unknown SGB Flag {:#X}", unknown);
 
                 false
             }
-            Some(Err(e)) => bail!("error occured while reading the SGB Flag {}", e),
-            None => bail!("unexpected EOF while reading the SGB Flag"),
         };
 
         // 0147 - Cartridge Type
-        if let Some(Ok(typ)) = reader.take(1).bytes().next() {
-            rom.mbc_type = FromPrimitive::from_u8(typ).context("unknown mbc type")?;
-        } else {
-            bail!("failed to parse the Cardridge Type");
-        }
+        rom.mbc_type = FromPrimitive::from_u8(header.byte("the Cartridge Type")?)
+            .ok_or_else(|| GbError::RomParse("unknown mbc type".to_string()))?;
 
         // 0148 - ROM Size
         // NOTE: バンク数を読み込んでいない
-        rom.rom_size = match reader.take(1).bytes().next() {
-            Some(Ok(n @ 0x00..=0x08)) => ((32 * 1024) << n) as usize,
-            Some(Ok(0x52)) => (1.1 * 1024.0 * 1024.0) as usize,
-            Some(Ok(0x53)) => (1.2 * 1024.0 * 1024.0) as usize,
-            Some(Ok(0x54)) => (1.5 * 1024.0 * 1024.0) as usize,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown ROM Size {:#X}", unknown);
+        rom.rom_size = match header.byte("the ROM Size")? {
+            n @ 0x00..=0x08 => ((32 * 1024) << n) as usize,
+            0x52 => (1.1 * 1024.0 * 1024.0) as usize,
+            0x53 => (1.2 * 1024.0 * 1024.0) as usize,
+            0x54 => (1.5 * 1024.0 * 1024.0) as usize,
+            unknown => {
+                log::warn!("unknown ROM Size {:#X}", unknown);
 
                 0
             }
-            Some(Err(e)) => bail!("error occured while reading the ROM Size {}", e),
-            None => bail!("unexpected EOF while reading the ROM Size"),
         };
 
         // 0149 - RAM Size
-        rom.ram_size = match reader.take(1).bytes().next() {
-            Some(Ok(0x00)) => 0_usize,
-            Some(Ok(0x01)) => 2 * 1024 * 1024_usize,
-            Some(Ok(0x02)) => 8 * 1024 * 1024_usize,
-            Some(Ok(0x03)) => 32 * 1024 * 1024_usize,
-            Some(Ok(0x04)) => 128 * 1024 * 1024_usize,
-            Some(Ok(0x05)) => 64 * 1024 * 1024_usize,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown RAM Size {:#X}", unknown);
+        rom.ram_size = match header.byte("the RAM Size")? {
+            0x00 => 0_usize,
+            0x01 => 2 * 1024_usize,
+            0x02 => 8 * 1024_usize,
+            0x03 => 32 * 1024_usize,
+            0x04 => 128 * 1024_usize,
+            0x05 => 64 * 1024_usize,
+            unknown => {
+                log::warn!("unknown RAM Size {:#X}", unknown);
 
                 0
             }
-            Some(Err(e)) => bail!("error occured while reading the RAM Size {}", e),
-            None => bail!("unexpected EOF while reading the RAM Size"),
         };
 
         // 014A - Destination Code
-        if let Some(Ok(code)) = reader.take(1).bytes().next() {
-            if let Some(destination_code) = FromPrimitive::from_u8(code) {
-                rom.destination_code = destination_code;
-            } else {
-                rom.destination_code = DestinationCode::Unknown;
-            }
-        } else {
-            bail!("failed to parse the Destination Code");
-        }
+        rom.destination_code = FromPrimitive::from_u8(header.byte("the Destination Code")?)
+            .unwrap_or(DestinationCode::Unknown);
 
         // 014B - Old Licensee Code
-        rom.old_licensee_code = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Old Licensee Code")??;
+        rom.old_licensee_code = header.byte("the Old Licensee Code")?;
 
         // 014C - Mask ROM Version number
-        rom.mask_rom_version_number = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Mask ROM Version number")??;
+        rom.mask_rom_version_number = header.byte("the Mask ROM Version number")?;
 
         // 014D - Header Checksum
-        rom.header_checksum = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Header Checksum")??;
+        rom.header_checksum = header.byte("the Header Checksum")?;
 
         // 014E-014F - Global Checksum
-        reader.read_exact(&mut rom.global_checksum[..])?;
-
-        reader.seek(SeekFrom::Start(0x0134))?;
+        header.read_exact(&mut rom.global_checksum[..], "the Global Checksum")?;
 
-        let mut chksum: u8 = 0;
+        let chksum = data[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
 
-        for _ in 0x0134..=0x014C {
-            if let Some(Ok(b)) = reader.take(1).bytes().next() {
-                chksum = chksum.wrapping_sub(b).wrapping_sub(1);
-            } else {
-                bail!("error occured while checking header chksum");
-            }
-        }
-
-        if rom.header_checksum != chksum {
-            bail!(
+        if strict && rom.header_checksum != chksum {
+            return Err(GbError::RomParse(format!(
                 "invalid checksum expected: {}, actual: {}",
-                rom.header_checksum,
-                chksum
-            );
+                rom.header_checksum, chksum
+            )));
         }
 
-        // TODO 先にraed_to_endしてから読み込んだほうがシンプル
-        reader.seek(SeekFrom::Start(0))?;
+        rom.data = data;
 
-        reader.read_to_end(&mut rom.data)?;
-
-        if rom.rom_size != rom.data.len() {
-            bail!(
+        if strict && rom.rom_size != rom.data.len() {
+            return Err(GbError::RomParse(format!(
                 "invalid rom size expected: {}, actual: {}",
                 rom.rom_size,
-                rom.data.len(),
-            );
+                rom.data.len()
+            )));
         }
 
         Ok(rom)
     }
 }
+
+#[cfg(feature = "std")]
+fn read_all<R: BufRead + Seek>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+// ヘッダを先頭から順に読み込む
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl HeaderReader<'_> {
+    fn read_exact(&mut self, buf: &mut [u8], name: &str) -> Result<()> {
+        let src = self
+            .data
+            .get(self.pos..self.pos + buf.len())
+            .ok_or_else(|| GbError::RomParse(format!("unexpected EOF while reading {}", name)))?;
+
+        buf.copy_from_slice(src);
+        self.pos += buf.len();
+
+        Ok(())
+    }
+
+    fn byte(&mut self, name: &str) -> Result<u8> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf, name)?;
+
+        Ok(buf[0])
+    }
+}
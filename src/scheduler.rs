@@ -0,0 +1,90 @@
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+// 同じサイクルに予約されたイベントは、ここでの宣言順に処理する
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EventKind {
+    // TIMAのオーバーフローから1Mサイクル後のTMAの再ロードと割り込み
+    TimerReload,
+    // TACで選択したDIVのビットの立ち下がり (TIMAを1進める)
+    TimerTick,
+    // 内部クロックでのシリアル転送の完了
+    SerialTransfer,
+    // OAM DMAの完了。予約されている間はCPUからHRAMとI/O以外が見えない
+    OamDma,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub cycle: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulerState {
+    pub now: u64,
+    pub events: Vec<Event>,
+}
+
+// 周辺機器の次の変化点を絶対サイクルで予約し、バスが進むのに合わせて順番に処理する。
+// PPUとAPUは毎ドット画素やサンプルを出すため、予約せずにバスが毎サイクル進める
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    now: u64,
+    // (cycle, kind) の昇順
+    events: Vec<Event>,
+}
+
+impl Scheduler {
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self) {
+        self.now = self.now.wrapping_add(1);
+    }
+
+    // 同じ種類のイベントが既に予約されている場合は置き換える
+    pub fn schedule(&mut self, delay: u64, kind: EventKind) {
+        self.cancel(kind);
+
+        let event = Event {
+            cycle: self.now.wrapping_add(delay),
+            kind,
+        };
+        let pos = self
+            .events
+            .partition_point(|e| (e.cycle, e.kind) <= (event.cycle, event.kind));
+
+        self.events.insert(pos, event);
+    }
+
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|e| e.kind != kind);
+    }
+
+    pub fn is_scheduled(&self, kind: EventKind) -> bool {
+        self.events.iter().any(|e| e.kind == kind)
+    }
+
+    // 現在のサイクルまでに発生するイベントを1つ取り出す
+    pub fn pop_due(&mut self) -> Option<EventKind> {
+        match self.events.first() {
+            Some(event) if event.cycle <= self.now => Some(self.events.remove(0).kind),
+            _ => None,
+        }
+    }
+
+    pub fn snapshot(&self) -> SchedulerState {
+        SchedulerState {
+            now: self.now,
+            events: self.events.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &SchedulerState) {
+        self.now = state.now;
+        self.events = state.events.clone();
+        self.events.sort_by_key(|e| (e.cycle, e.kind));
+    }
+}
@@ -0,0 +1,25 @@
+use alloc::string::String;
+
+// シリアルポートから送信されたバイトを受け取る。
+// テストROMの出力の監視や、通信ケーブルの相手への転送に使う
+pub trait SerialSink {
+    fn on_byte(&mut self, b: u8);
+}
+
+// 既定のSink。送信されたバイトを文字列として溜めておく
+#[derive(Debug, Default)]
+pub struct BufferSink {
+    output: String,
+}
+
+impl BufferSink {
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+}
+
+impl SerialSink for BufferSink {
+    fn on_byte(&mut self, b: u8) {
+        self.output.push(b as char);
+    }
+}
@@ -1,6 +1,11 @@
+use crate::scheduler::{EventKind, Scheduler};
 use bitmatch::bitmatch;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+
+// TIMAがオーバーフローしてからTMAが再ロードされるまでのサイクル数 (1Mサイクル)
+const RELOAD_DELAY: u64 = 4;
 
 #[derive(FromPrimitive, Debug, Copy, Clone)]
 enum Clock {
@@ -10,82 +15,139 @@ enum Clock {
     Clock16384 = 0b11,
 }
 
+impl Clock {
+    // TIMAを進めるDIVのカウンタのビット。このビットの立ち下がりでTIMAが1進む
+    fn bit(self) -> u16 {
+        match self {
+            Clock::Clock4096 => 1 << 9,
+            Clock::Clock262144 => 1 << 3,
+            Clock::Clock65536 => 1 << 5,
+            Clock::Clock16384 => 1 << 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimerState {
+    pub counter: u16,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    pub int: bool,
+}
+
+// DIVのカウンタは最後にリセットされたサイクルからの経過で求め、
+// TIMAは選択中のビットの次の立ち下がりをスケジューラに予約して進める
 #[derive(Debug)]
 pub struct Timer {
-    counter: u16,
+    // DIVに書き込まれた (カウンタが0になった) サイクル
+    div_base: u64,
     tima: u8,
     tma: u8,
     enable: bool,
     clock: Clock,
-    prev: bool,
     pub int: bool,
 }
 
 impl Default for Timer {
     fn default() -> Self {
         Self {
-            counter: 0,
+            div_base: 0,
             tima: 0,
             tma: 0,
             enable: false,
             clock: Clock::Clock4096,
             int: false,
-            prev: false,
         }
     }
 }
 
 impl Timer {
-    fn sync(&mut self) {
-        let mut cur = false;
-
-        if self.enable {
-            let bit = match self.clock {
-                Clock::Clock4096 => 1 << 9,
-                Clock::Clock262144 => 1 << 3,
-                Clock::Clock65536 => 1 << 5,
-                Clock::Clock16384 => 1 << 7,
-            };
-
-            cur = self.counter & bit > 0;
+    fn counter(&self, scheduler: &Scheduler) -> u16 {
+        scheduler.now().wrapping_sub(self.div_base) as u16
+    }
+
+    // TIMAのクロックとして使われている信号 (有効ビットと選択中のビットのAND)
+    fn signal(&self, scheduler: &Scheduler) -> bool {
+        self.enable && self.counter(scheduler) & self.clock.bit() > 0
+    }
+
+    // 選択中のビットの次の立ち下がりを予約し直す
+    fn reschedule(&mut self, scheduler: &mut Scheduler) {
+        if !self.enable {
+            scheduler.cancel(EventKind::TimerTick);
+            return;
         }
 
-        if self.prev && !cur {
-            self.tima = self.tima.wrapping_add(1);
+        let period = (self.clock.bit() as u64) << 1;
+        let counter = self.counter(scheduler) as u64;
+
+        scheduler.schedule(period - counter % period, EventKind::TimerTick);
+    }
+
+    fn increment(&mut self, scheduler: &mut Scheduler) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
 
-            if self.counter % 4 == 0 && self.tima == 0 {
-                self.tima = self.tma;
-                self.int = true;
-                // println!("{:?}", self);
-            }
+        self.tima = tima;
+
+        // オーバーフロー直後のTIMAは0のままで、再ロードと割り込みは1Mサイクル後
+        if overflow {
+            scheduler.schedule(RELOAD_DELAY, EventKind::TimerReload);
         }
+    }
 
-        self.prev = cur;
+    // EventKind::TimerTick の処理
+    pub fn tick(&mut self, scheduler: &mut Scheduler) {
+        self.increment(scheduler);
+        self.reschedule(scheduler);
     }
 
-    pub fn tick(&mut self) {
-        self.counter = self.counter.wrapping_add(1);
+    pub fn snapshot(&self, scheduler: &Scheduler) -> TimerState {
+        TimerState {
+            counter: self.counter(scheduler),
+            tima: self.tima,
+            tma: self.tma,
+            tac: self.read_tac(),
+            int: self.int,
+        }
+    }
 
-        self.sync();
+    // 予約済みのイベントはスケジューラの状態に含まれるため、先にスケジューラを復元しておく
+    pub fn restore(&mut self, state: &TimerState, scheduler: &Scheduler) {
+        self.div_base = scheduler.now().wrapping_sub(state.counter as u64);
+        self.tima = state.tima;
+        self.tma = state.tma;
+        self.set_tac(state.tac);
+        self.int = state.int;
+    }
 
-        // println!("{:?}", self);
+    pub fn read_div(&self, scheduler: &Scheduler) -> u8 {
+        (self.counter(scheduler) >> 8) as u8
     }
 
-    pub fn read_div(&self) -> u8 {
-        (self.counter >> 8) as u8
+    pub fn write_div(&mut self, _val: u8, scheduler: &mut Scheduler) {
+        // 選択中のビットが立っていた場合、リセットによる立ち下がりでTIMAが進む
+        if self.signal(scheduler) {
+            self.increment(scheduler);
+        }
+
+        self.div_base = scheduler.now();
+        self.reschedule(scheduler);
     }
 
-    pub fn write_div(&mut self, _val: u8) {
-        self.counter = 0;
+    // EventKind::TimerReload の処理
+    pub fn reload(&mut self) {
+        self.tima = self.tma;
+        self.int = true;
     }
 
     pub fn read_tima(&self) -> u8 {
         self.tima
     }
 
-    pub fn write_tima(&mut self, val: u8) {
-        self.sync();
-
+    pub fn write_tima(&mut self, val: u8, scheduler: &mut Scheduler) {
+        // 再ロード待ちの間にTIMAへ書き込むと再ロードと割り込みは起きない
+        scheduler.cancel(EventKind::TimerReload);
         self.tima = val;
     }
 
@@ -95,8 +157,6 @@ impl Timer {
 
     pub fn write_tma(&mut self, val: u8) {
         self.tma = val;
-
-        self.sync();
     }
 
     #[bitmatch]
@@ -107,8 +167,21 @@ impl Timer {
         bitpack!("00000ess")
     }
 
+    pub fn write_tac(&mut self, val: u8, scheduler: &mut Scheduler) {
+        let prev = self.signal(scheduler);
+
+        self.set_tac(val);
+
+        // 無効化やクロックの切り替えで信号が立ち下がった場合もTIMAが進む
+        if prev && !self.signal(scheduler) {
+            self.increment(scheduler);
+        }
+
+        self.reschedule(scheduler);
+    }
+
     #[bitmatch]
-    pub fn write_tac(&mut self, val: u8) {
+    fn set_tac(&mut self, val: u8) {
         #[bitmatch]
         let "?????ess" = val;
 
@@ -117,7 +190,7 @@ impl Timer {
         if let Some(clock) = FromPrimitive::from_u8(s) {
             self.clock = clock;
         } else {
-            eprintln!("unknown clock {}", s);
+            log::warn!("unknown clock {}", s);
         }
     }
 }
@@ -1,6 +1,44 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
 pub fn bytes_to_hex(bytes: &[u8]) -> String {
     bytes
         .iter()
         .map(|&b| format!("{:02X}", b))
         .collect::<String>()
 }
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum MemoryFill {
+    #[default]
+    Zero,
+    Pattern(u8),
+    // シード付きの疑似乱数 (xorshift64)
+    Random(u64),
+}
+
+impl MemoryFill {
+    pub fn bytes(self) -> Box<dyn Iterator<Item = u8>> {
+        match self {
+            MemoryFill::Zero => Box::new(core::iter::repeat(0)),
+            MemoryFill::Pattern(val) => Box::new(core::iter::repeat(val)),
+            MemoryFill::Random(seed) => {
+                // xorshiftは状態0から抜け出せないため固定値で置き換える
+                let mut state = if seed == 0 {
+                    0x9E37_79B9_7F4A_7C15
+                } else {
+                    seed
+                };
+
+                Box::new(core::iter::from_fn(move || {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+
+                    Some((state >> 32) as u8)
+                }))
+            }
+        }
+    }
+}
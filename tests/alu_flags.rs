@@ -0,0 +1,207 @@
+mod common;
+
+use common::{boot, rom, run_to};
+use gb::cpu::CpuState;
+
+const Z: u8 = 0x80;
+const N: u8 = 0x40;
+const H: u8 = 0x20;
+const C: u8 = 0x10;
+
+// `op`を1命令だけ実行した後のCPUの状態
+fn run_op(op: &[u8], setup: impl Fn(&mut CpuState)) -> CpuState {
+    let mut program = op.to_vec();
+    program.extend_from_slice(&[0x18, 0xFE]); // JR -2
+
+    let mut gb = boot(&rom(&program));
+    let mut state = gb.cpu_state();
+    setup(&mut state);
+    gb.set_cpu_state(&state);
+
+    run_to(&mut gb, 0x0100 + op.len() as u16);
+
+    gb.cpu_state()
+}
+
+fn add_hl_bc(hl: u16, bc: u16, f: u8) -> CpuState {
+    run_op(&[0x09], |s| {
+        s.hl = hl;
+        s.bc = bc;
+        s.f = f;
+    })
+}
+
+fn add_sp(sp: u16, e: u8) -> CpuState {
+    run_op(&[0xE8, e], |s| {
+        s.sp = sp;
+        s.f = Z | N;
+    })
+}
+
+fn ld_hl_sp(sp: u16, e: u8) -> CpuState {
+    run_op(&[0xF8, e], |s| {
+        s.sp = sp;
+        s.f = Z | N;
+    })
+}
+
+#[test]
+fn add_hl_half_carry_from_bit_11() {
+    let state = add_hl_bc(0x0FFF, 0x0001, 0);
+    assert_eq!(state.hl, 0x1000);
+    assert_eq!(state.f, H);
+
+    // 下位バイトの桁上がりはHに影響しない
+    let state = add_hl_bc(0x00FF, 0x0001, 0);
+    assert_eq!(state.hl, 0x0100);
+    assert_eq!(state.f, 0);
+}
+
+#[test]
+fn add_hl_carry_from_bit_15() {
+    let state = add_hl_bc(0x8000, 0x8000, 0);
+    assert_eq!(state.hl, 0x0000);
+    assert_eq!(state.f, C);
+}
+
+#[test]
+fn add_hl_preserves_z_and_clears_n() {
+    // 結果が0でもZは変化しない
+    let state = add_hl_bc(0xFFFF, 0x0001, 0);
+    assert_eq!(state.hl, 0x0000);
+    assert_eq!(state.f, H | C);
+
+    let state = add_hl_bc(0xFFFF, 0x0001, Z | N);
+    assert_eq!(state.hl, 0x0000);
+    assert_eq!(state.f, Z | H | C);
+}
+
+#[test]
+fn add_sp_flags_from_low_byte() {
+    // bit 11をまたいでも、H/Cは下位バイトのbit 3/bit 7から決まる
+    let state = add_sp(0x0FF0, 0x10);
+    assert_eq!(state.sp, 0x1000);
+    assert_eq!(state.f, C);
+
+    let state = add_sp(0x000F, 0x01);
+    assert_eq!(state.sp, 0x0010);
+    assert_eq!(state.f, H);
+
+    // 負の値でも符号なしの下位バイトとして加算した桁上がりを見る
+    let state = add_sp(0x0001, 0xFF);
+    assert_eq!(state.sp, 0x0000);
+    assert_eq!(state.f, H | C);
+}
+
+#[test]
+fn ld_hl_sp_flags_from_low_byte() {
+    let state = ld_hl_sp(0x0FF0, 0x10);
+    assert_eq!(state.hl, 0x1000);
+    assert_eq!(state.sp, 0x0FF0);
+    assert_eq!(state.f, C);
+
+    let state = ld_hl_sp(0x000F, 0x01);
+    assert_eq!(state.hl, 0x0010);
+    assert_eq!(state.f, H);
+}
+
+#[test]
+fn sp_plus_minus_one_flags() {
+    // n=0xFF (-1)。下位バイトの0x00 + 0xFFは桁上がりしない
+    let state = add_sp(0x0100, 0xFF);
+    assert_eq!(state.sp, 0x00FF);
+    assert_eq!(state.f, 0);
+
+    let state = ld_hl_sp(0x0100, 0xFF);
+    assert_eq!(state.hl, 0x00FF);
+    assert_eq!(state.sp, 0x0100);
+    assert_eq!(state.f, 0);
+
+    // 0x0F + 0xFFはbit 3とbit 7の両方から桁上がりする
+    let state = add_sp(0x000F, 0xFF);
+    assert_eq!(state.sp, 0x000E);
+    assert_eq!(state.f, H | C);
+
+    let state = ld_hl_sp(0x000F, 0xFF);
+    assert_eq!(state.hl, 0x000E);
+    assert_eq!(state.sp, 0x000F);
+    assert_eq!(state.f, H | C);
+}
+
+#[test]
+fn hl_and_sp_adds_disagree_on_the_same_operands() {
+    assert_eq!(add_hl_bc(0x0FF0, 0x0010, 0).f, H);
+    assert_eq!(add_sp(0x0FF0, 0x10).f, C);
+}
+
+// CBプレフィックス付きのローテートの期待値 (結果, キャリー)
+fn rotate(base: u8, x: u8, carry: bool) -> (u8, bool) {
+    match base {
+        0x00 => (x.rotate_left(1), x & 0x80 != 0),
+        0x08 => (x.rotate_right(1), x & 0x01 != 0),
+        0x10 => (x << 1 | carry as u8, x & 0x80 != 0),
+        0x18 => (x >> 1 | (carry as u8) << 7, x & 0x01 != 0),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn cb_rotates_on_a_register_and_hl() {
+    let ops = [(0x00, "RLC"), (0x08, "RRC"), (0x10, "RL"), (0x18, "RR")];
+
+    for &(base, name) in &ops {
+        for &x in &[0x00, 0x80, 0x01] {
+            for &carry in &[false, true] {
+                let (result, carry_out) = rotate(base, x, carry);
+                let f = if result == 0 { Z } else { 0 } | if carry_out { C } else { 0 };
+                // 入力のNとHは結果に残らない
+                let f_in = N | H | if carry { C } else { 0 };
+
+                let state = run_op(&[0xCB, base], |s| {
+                    s.bc = (x as u16) << 8;
+                    s.f = f_in;
+                });
+                assert_eq!(
+                    (state.bc >> 8) as u8,
+                    result,
+                    "{} B {:#04X} c={}",
+                    name,
+                    x,
+                    carry
+                );
+                assert_eq!(state.f, f, "{} B {:#04X} c={}", name, x, carry);
+
+                // LD (HL), x; <op> (HL); LD A, (HL)
+                let state = run_op(&[0x36, x, 0xCB, base + 6, 0x7E], |s| {
+                    s.hl = 0xC000;
+                    s.f = f_in;
+                });
+                assert_eq!(state.a, result, "{} (HL) {:#04X} c={}", name, x, carry);
+                assert_eq!(state.f, f, "{} (HL) {:#04X} c={}", name, x, carry);
+            }
+        }
+    }
+}
+
+#[test]
+fn xor_a_clears_a_and_sets_only_z() {
+    let state = run_op(&[0xAF], |s| {
+        s.a = 0x5A;
+        s.f = N | H | C;
+    });
+
+    assert_eq!(state.a, 0);
+    assert_eq!(state.f, Z);
+}
+
+#[test]
+fn and_sets_h_while_or_and_xor_clear_it() {
+    for &(op, name, h) in &[(0xE6, "AND", H), (0xF6, "OR", 0), (0xEE, "XOR", 0)] {
+        let state = run_op(&[op, 0x0F], |s| {
+            s.a = 0x3C;
+            s.f = N | H | C;
+        });
+
+        assert_eq!(state.f, h, "{}", name);
+    }
+}
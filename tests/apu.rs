@@ -0,0 +1,95 @@
+use gb::apu::{sample_to_i16, Apu};
+
+// 電源を入れ、両チャンネルを左右に出して最大音量にする
+fn apu() -> Apu {
+    let mut apu = Apu::default();
+
+    apu.write(0xFF26, 0x80).unwrap();
+    apu.write(0xFF24, 0x77).unwrap();
+    apu.write(0xFF25, 0xFF).unwrap();
+
+    apu
+}
+
+// 50%デューティ、音量15、エンベロープ無しで鳴らす。`base`はNRx1のアドレス
+fn trigger_square(apu: &mut Apu, base: u16) {
+    apu.write(base, 0x80).unwrap();
+    apu.write(base + 1, 0xF0).unwrap();
+    apu.write(base + 2, 0x00).unwrap();
+    apu.write(base + 3, 0x87).unwrap();
+}
+
+fn samples(apu: &mut Apu) -> Vec<f32> {
+    // 1/60秒分
+    for _ in 0..70224 {
+        apu.tick();
+    }
+
+    apu.drain_samples()
+}
+
+#[test]
+fn muted_channel_1_produces_silence() {
+    let mut audible = apu();
+    trigger_square(&mut audible, 0xFF11);
+    assert!(samples(&mut audible).iter().any(|&s| s != 0.0));
+
+    let mut apu = apu();
+    apu.set_channel_enabled(1, false);
+    trigger_square(&mut apu, 0xFF11);
+
+    let samples = samples(&mut apu);
+    assert!(!samples.is_empty());
+    assert!(samples.iter().all(|&s| s == 0.0));
+
+    // ミュートしてもチャンネル自体は動いている
+    assert!(apu.channel_state(1).unwrap().on);
+    assert!(!apu.channel_enabled(1));
+}
+
+#[test]
+fn other_channels_play_while_channel_1_is_muted() {
+    let mut muted = apu();
+    muted.set_channel_enabled(1, false);
+    trigger_square(&mut muted, 0xFF11);
+    trigger_square(&mut muted, 0xFF16);
+
+    let mut only_2 = apu();
+    trigger_square(&mut only_2, 0xFF16);
+
+    let muted = samples(&mut muted);
+    assert!(muted.iter().any(|&s| s != 0.0));
+    assert_eq!(muted, samples(&mut only_2));
+}
+
+#[test]
+fn full_scale_samples_map_to_the_i16_limits() {
+    assert_eq!(sample_to_i16(1.0), i16::MAX);
+    assert_eq!(sample_to_i16(-1.0), -i16::MAX);
+    assert_eq!(sample_to_i16(0.0), 0);
+    assert_eq!(sample_to_i16(0.5), 16384);
+
+    // 範囲外はクランプする
+    assert_eq!(sample_to_i16(1.5), i16::MAX);
+    assert_eq!(sample_to_i16(-1.5), -i16::MAX);
+}
+
+#[test]
+fn drain_i16_converts_the_same_samples_as_drain_samples() {
+    let mut float = apu();
+    trigger_square(&mut float, 0xFF11);
+    let float = samples(&mut float);
+
+    let mut int = apu();
+    trigger_square(&mut int, 0xFF11);
+    for _ in 0..70224 {
+        int.tick();
+    }
+    let int = int.drain_i16();
+
+    assert_eq!(
+        int,
+        float.into_iter().map(sample_to_i16).collect::<Vec<_>>()
+    );
+    assert!(int.iter().any(|&s| s != 0));
+}
@@ -0,0 +1,21 @@
+mod common;
+
+use common::{boot, rom};
+use gb::error::GbError;
+
+// 書き込み先の容量が無い場合は、エラーを返して録音を止める
+#[cfg(target_os = "linux")]
+#[test]
+fn recorder_failure_is_returned_from_drain_samples() {
+    let mut gb = boot(&rom(&[0x18, 0xFE])); // JR -2
+    gb.record_audio_wav("/dev/full").unwrap();
+
+    // バッファが溢れて書き出すまでは成功する
+    let err = (0..60)
+        .find_map(|_| gb.run_frame().err())
+        .expect("recording to a full device should fail");
+
+    assert!(matches!(err, GbError::Io(_)));
+    // 録音は止まっている
+    assert!(gb.drain_samples().is_ok());
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::ROM_SIZE;
+use gb::boot_palette::{self, BOOT_PALETTE_COUNT, BUTTON_PALETTES, DEFAULT_BOOT_PALETTE};
+use gb::gb::GbBuilder;
+use gb::rom::Rom;
+
+fn rom(title: &[u8], old_licensee: u8, new_licensee: &[u8; 2]) -> Rom {
+    let mut data = vec![0; ROM_SIZE];
+
+    data[0x0134..0x0134 + title.len()].copy_from_slice(title);
+    data[0x0144..0x0146].copy_from_slice(new_licensee);
+    data[0x014B] = old_licensee;
+
+    Rom::from_bytes_unchecked(data).unwrap()
+}
+
+#[test]
+fn unique_checksums_pick_their_combination() {
+    assert_eq!(boot_palette::lookup(&rom(b"TETRIS", 0x01, b"\0\0")), 3);
+    assert_eq!(boot_palette::lookup(&rom(b"ZELDA", 0x01, b"\0\0")), 44);
+    assert_eq!(boot_palette::lookup(&rom(b"POKEMON RED", 0x33, b"01")), 13);
+}
+
+#[test]
+fn colliding_checksums_use_the_fourth_letter() {
+    // チェックサムはどれも0x46で、4文字目の'E'と'R'がそれぞれ別の組み合わせを選ぶ
+    assert_eq!(boot_palette::title_checksum(b"SUPER MARIOLAND"), 0x46);
+    assert_eq!(boot_palette::title_checksum(b"SUPRR MARIOLAN7"), 0x46);
+    assert_eq!(boot_palette::title_checksum(b"SUPAR MARIOLANH"), 0x46);
+
+    assert_eq!(
+        boot_palette::lookup(&rom(b"SUPER MARIOLAND", 0x01, b"\0\0")),
+        22
+    );
+    assert_eq!(
+        boot_palette::lookup(&rom(b"SUPRR MARIOLAN7", 0x01, b"\0\0")),
+        46
+    );
+    // 4文字目がどれとも合わなければ既定になる
+    assert_eq!(
+        boot_palette::lookup(&rom(b"SUPAR MARIOLANH", 0x01, b"\0\0")),
+        DEFAULT_BOOT_PALETTE
+    );
+}
+
+#[test]
+fn other_licensees_get_the_default() {
+    assert_eq!(
+        boot_palette::lookup(&rom(b"TETRIS", 0x33, b"08")),
+        DEFAULT_BOOT_PALETTE
+    );
+    assert_eq!(
+        boot_palette::lookup(&rom(b"TETRIS", 0x08, b"\0\0")),
+        DEFAULT_BOOT_PALETTE
+    );
+}
+
+#[test]
+fn default_combination_is_green_bg_with_red_objects() {
+    let palette = boot_palette::palette(DEFAULT_BOOT_PALETTE).unwrap();
+
+    assert_eq!(palette.bg[0], [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(palette.bg[1], [0x7B, 0xFF, 0x31, 0xFF]);
+    assert_eq!(palette.bg[2], [0x00, 0x63, 0xC6, 0xFF]);
+    assert_eq!(palette.obj0, palette.obj1);
+    assert_eq!(palette.obj0[1], [0xFF, 0x84, 0x84, 0xFF]);
+}
+
+#[test]
+fn every_combination_and_button_palette_is_in_range() {
+    for index in 0..BOOT_PALETTE_COUNT {
+        assert!(boot_palette::palette(index).is_some());
+    }
+    for &(name, index) in BUTTON_PALETTES.iter() {
+        assert!(index < BOOT_PALETTE_COUNT, "{}", name);
+    }
+    assert!(boot_palette::palette(BOOT_PALETTE_COUNT).is_none());
+}
+
+#[test]
+fn unknown_index_is_rejected() {
+    let mut gb = GbBuilder::new()
+        .strict(false)
+        .build(&vec![0; ROM_SIZE])
+        .unwrap();
+
+    assert!(gb.set_dmg_palette_by_index(BOOT_PALETTE_COUNT).is_err());
+    assert!(gb.set_dmg_palette_by_index(BOOT_PALETTE_COUNT - 1).is_ok());
+}
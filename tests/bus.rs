@@ -0,0 +1,57 @@
+mod common;
+
+use gb::bus::{Bus, Model};
+
+// OAMに0x00-0x9Fを書き込んだバス
+fn bus_with_oam(model: Model) -> Bus {
+    let mut bus = common::bus();
+    bus.set_model(model);
+
+    for i in 0..0xA0 {
+        bus.write(0xFE00 + i, i as u8).unwrap();
+    }
+
+    bus
+}
+
+fn oam(bus: &Bus) -> Vec<u8> {
+    (0..0xA0).map(|i| bus.read(0xFE00 + i).unwrap()).collect()
+}
+
+#[test]
+fn unusable_region_reads_per_model() {
+    // PPUはVBlankから始まるため、DMGではOAMと同じく読める状態
+    let dmg = bus_with_oam(Model::Dmg);
+    assert_eq!(dmg.read(0xFEA0).unwrap(), 0x00);
+    assert_eq!(dmg.read(0xFEFF).unwrap(), 0x00);
+
+    let cgb = bus_with_oam(Model::Cgb);
+    assert_eq!(cgb.read(0xFEA0).unwrap(), 0xAA);
+    assert_eq!(cgb.read(0xFEB3).unwrap(), 0xBB);
+    assert_eq!(cgb.read(0xFEFF).unwrap(), 0xFF);
+}
+
+#[test]
+fn unusable_region_reads_open_bus_during_oam_scan_on_dmg() {
+    let mut dmg = bus_with_oam(Model::Dmg);
+    dmg.tick().unwrap();
+
+    assert_eq!(dmg.read(0xFF41).unwrap() & 0b11, 2);
+    assert_eq!(dmg.read(0xFEA0).unwrap(), 0xFF);
+}
+
+#[test]
+fn unusable_region_read_during_oam_scan_corrupts_oam_on_dmg_only() {
+    for &(model, corrupted) in &[(Model::Dmg, true), (Model::Cgb, false)] {
+        let mut bus = bus_with_oam(model);
+        let before = oam(&bus);
+
+        // OAMスキャンの4行目でアクセスする (cpu_readがさらに4サイクル進める)
+        for _ in 0..12 {
+            bus.tick().unwrap();
+        }
+        bus.cpu_read(0xFEA0).unwrap();
+
+        assert_eq!(oam(&bus) != before, corrupted, "{:?}", model);
+    }
+}
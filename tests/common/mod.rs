@@ -0,0 +1,92 @@
+#![allow(dead_code)]
+
+use gb::bus::Bus;
+use gb::cpu::Cpu;
+use gb::gb::{Gb, GbBuilder};
+use gb::mbc::new_mbc;
+use gb::ppu::Ppu;
+use gb::rom::Rom;
+use rustyline::Editor;
+
+pub const ROM_SIZE: usize = 0x8000;
+
+pub const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// 0x0100から`program`を置いた32KiBのROMONLYのROM
+pub fn rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; ROM_SIZE];
+
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+
+    rom
+}
+
+// ヘッダとグローバルチェックサムを埋め直す
+pub fn fix_checksums(data: &mut [u8]) {
+    data[0x014D] = data[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+
+    let global = data
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+        .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+
+    data[0x014E..=0x014F].copy_from_slice(&global.to_be_bytes());
+}
+
+// ロゴ、タイトル、チェックサムを埋めてヘッダの検証を通るようにする。
+// エントリポイントはJR -2で止めておく
+pub fn valid_rom() -> Vec<u8> {
+    let mut data = rom(&[0x18, 0xFE]);
+
+    data[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+    data[0x0134..0x0138].copy_from_slice(b"TEST");
+    fix_checksums(&mut data);
+
+    data
+}
+
+// ヘッダを検証せずに構築し、0x0100から実行できる状態にする
+pub fn boot(rom: &[u8]) -> Gb {
+    let mut gb = GbBuilder::new().strict(false).build(rom).unwrap();
+
+    gb.reset().unwrap();
+
+    gb
+}
+
+// `addr`の命令に到達するまで進める
+pub fn run_to(gb: &mut Gb, addr: u16) {
+    assert!(
+        gb.run_until(100_000, |gb| gb.pc() == addr).unwrap(),
+        "did not reach {:#06X}",
+        addr
+    );
+}
+
+// ヘッダを検証せずに`data`を読み込んだバス
+pub fn bus_with_rom(data: Vec<u8>) -> Bus {
+    let rom = Rom::from_bytes_unchecked(data).unwrap();
+
+    Bus::new(Ppu::new(), new_mbc(rom))
+}
+
+// 中身が全て0のROMONLYのカートリッジを挿したバス
+pub fn bus() -> Bus {
+    bus_with_rom(vec![0; ROM_SIZE])
+}
+
+pub fn cpu_with_rom(data: Vec<u8>) -> Cpu {
+    Cpu::new(bus_with_rom(data), Editor::new())
+}
+
+// `bus()`に繋いだCPU。PCやレジスタはset_stateで設定する
+pub fn cpu() -> Cpu {
+    cpu_with_rom(vec![0; ROM_SIZE])
+}
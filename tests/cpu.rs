@@ -0,0 +1,173 @@
+mod common;
+
+use common::{boot, cpu, cpu_with_rom, rom, run_to, ROM_SIZE};
+use gb::bus::Model;
+use gb::cpu::{Cpu, CpuState};
+
+fn state() -> CpuState {
+    CpuState {
+        a: 0x12,
+        f: 0xA0,
+        bc: 0x3456,
+        de: 0x789A,
+        hl: 0xBCDE,
+        sp: 0xDFF0,
+        pc: 0x0150,
+        stalls: 3,
+        ime: false,
+        ime_pending: true,
+        halt: true,
+        halt_bug: false,
+    }
+}
+
+#[test]
+fn state_round_trips() {
+    let mut cpu = cpu();
+
+    cpu.set_state(state());
+
+    assert_eq!(cpu.state(), state());
+}
+
+#[test]
+fn set_state_clears_the_low_nibble_of_f() {
+    let mut cpu = cpu();
+
+    cpu.set_state(CpuState { f: 0xFF, ..state() });
+
+    assert_eq!(cpu.state().f, 0xF0);
+}
+
+#[test]
+fn pop_af_clears_the_low_nibble_of_f() {
+    let mut gb = boot(&rom(&[
+        0x01, 0xFF, 0x12, // LD BC, $12FF
+        0xC5, // PUSH BC
+        0xF1, // POP AF
+        0x18, 0xFE, // JR -2
+    ]));
+    run_to(&mut gb, 0x0105);
+
+    let state = gb.cpu_state();
+    assert_eq!(state.a, 0x12);
+    assert_eq!(state.f, 0xF0);
+}
+
+// OAMに0x00-0x9Fを書き込み、OAMスキャンの4行目で0xC000のINC HLを実行する
+fn inc_hl_during_oam_scan(model: Model, hl: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut cpu = cpu();
+    cpu.bus.set_model(model);
+    for i in 0..0xA0 {
+        cpu.bus.write(0xFE00 + i, i as u8).unwrap();
+    }
+    cpu.bus.write(0xC000, 0x23).unwrap();
+
+    let mut state = cpu.state();
+    state.pc = 0xC000;
+    state.hl = hl;
+    cpu.set_state(state);
+
+    let oam = |cpu: &Cpu| -> Vec<u8> {
+        (0..0xA0)
+            .map(|i| cpu.bus.read(0xFE00 + i).unwrap())
+            .collect()
+    };
+    let before = oam(&cpu);
+
+    // PPUはVBlankから始まり、1サイクル後にOAMスキャンに入る。
+    // 命令のフェッチでさらに4サイクル進む
+    for _ in 0..12 {
+        cpu.bus.tick().unwrap();
+    }
+    cpu.tick().unwrap();
+    assert_eq!(cpu.state().hl, hl.wrapping_add(1));
+
+    (before, oam(&cpu))
+}
+
+#[test]
+fn inc_hl_pointing_into_oam_corrupts_the_scanned_row_on_dmg() {
+    let (before, after) = inc_hl_during_oam_scan(Model::Dmg, 0xFE20);
+
+    let changed: Vec<usize> = (0..0xA0).filter(|&i| before[i] != after[i]).collect();
+    assert!(!changed.is_empty());
+
+    // 壊れるのはスキャン中の1行 (8バイト) だけで、先頭のワード以外は前の行の写しになる
+    let row = changed[0] / 8 * 8;
+    assert!(row > 0);
+    assert!(changed.iter().all(|&i| i / 8 * 8 == row));
+    assert_eq!(after[row + 2..row + 8], before[row - 6..row]);
+
+    let word = |i: usize| u16::from_le_bytes([before[i], before[i + 1]]);
+    let (a, b, c) = (word(row), word(row - 8), word(row - 4));
+    assert_eq!(
+        u16::from_le_bytes([after[row], after[row + 1]]),
+        ((a ^ c) & (b ^ c)) ^ c
+    );
+}
+
+#[test]
+fn inc_hl_leaves_oam_alone_outside_oam_or_on_cgb() {
+    let (before, after) = inc_hl_during_oam_scan(Model::Dmg, 0xC100);
+    assert_eq!(before, after);
+
+    let (before, after) = inc_hl_during_oam_scan(Model::Cgb, 0xFE20);
+    assert_eq!(before, after);
+}
+
+// `pc`から1命令実行する。オープンバスの0xFFはRST $38として実行される
+fn fetch_from(cpu: &mut Cpu, pc: u16) {
+    let mut state = cpu.state();
+    state.pc = pc;
+    state.sp = 0xDFF0;
+    cpu.set_state(state);
+
+    cpu.tick().unwrap();
+}
+
+fn assert_rst_38_from(cpu: &Cpu, pc: u16) {
+    let state = cpu.state();
+    assert_eq!(state.pc, 0x0038);
+    assert_eq!(state.sp, 0xDFEE);
+
+    let ret = pc.wrapping_add(1).to_le_bytes();
+    assert_eq!(cpu.bus.read(0xDFEE).unwrap(), ret[0]);
+    assert_eq!(cpu.bus.read(0xDFEF).unwrap(), ret[1]);
+}
+
+#[test]
+fn opcode_fetch_from_disabled_cartridge_ram_runs_the_open_bus_opcode() {
+    // MBC1+RAM、RAMは無効のまま
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = 0x02;
+    data[0x0149] = 0x02;
+    let mut cpu = cpu_with_rom(data);
+
+    fetch_from(&mut cpu, 0xA000);
+
+    assert_rst_38_from(&cpu, 0xA000);
+}
+
+#[test]
+fn opcode_fetch_from_unmapped_io_runs_the_open_bus_opcode() {
+    let mut cpu = cpu();
+
+    fetch_from(&mut cpu, 0xFF03);
+
+    assert_rst_38_from(&cpu, 0xFF03);
+}
+
+#[test]
+fn opcode_fetch_blocked_by_oam_dma_runs_the_open_bus_opcode() {
+    let mut cpu = cpu();
+    cpu.bus.write(0xC000, 0x00).unwrap(); // NOP
+    cpu.bus.write(0xFF46, 0xC1).unwrap();
+
+    fetch_from(&mut cpu, 0xC000);
+
+    // DMA中はスタックへの書き込みも届かないため、PCとSPだけを確かめる
+    let state = cpu.state();
+    assert_eq!(state.pc, 0x0038);
+    assert_eq!(state.sp, 0xDFEE);
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{boot, cpu, rom, run_to};
+use gb::cpu::debugger::{parse_break, parse_set};
+use gb::cpu::expr::{Comparison, Condition, Expr, Register};
+use gb::cpu::Breakpoint;
+
+#[test]
+fn set_takes_a_hex_address_and_byte() {
+    assert_eq!(parse_set("0xFF43 0x10"), Some((0xFF43, 0x10)));
+    assert_eq!(parse_set("ff47 e4"), Some((0xFF47, 0xE4)));
+    // MBCのレジスタもBus::write経由で書き込める
+    assert_eq!(parse_set("2000 3"), Some((0x2000, 0x03)));
+}
+
+#[test]
+fn set_rejects_malformed_arguments() {
+    assert_eq!(parse_set(""), None);
+    assert_eq!(parse_set("0xFF43"), None);
+    assert_eq!(parse_set("0xFF43 0x100"), None);
+    assert_eq!(parse_set("0x10000 0x00"), None);
+    assert_eq!(parse_set("0xFF43 0x10 0x20"), None);
+    assert_eq!(parse_set("SCX 0x10"), None);
+}
+
+#[test]
+fn break_takes_an_optional_condition() {
+    assert_eq!(
+        parse_break("0x0150"),
+        Some(Breakpoint {
+            addr: 0x0150,
+            condition: None,
+        })
+    );
+    assert_eq!(
+        parse_break("$C000 if (HL) != $FF"),
+        Some(Breakpoint {
+            addr: 0xC000,
+            condition: Some(Condition {
+                lhs: Expr::Indirect(Register::HL),
+                cmp: Comparison::Ne,
+                rhs: 0xFF,
+            }),
+        })
+    );
+    assert_eq!(parse_break("0x0150 if"), None);
+    assert_eq!(parse_break("0x0150 if A"), None);
+}
+
+#[test]
+fn expressions_distinguish_registers_from_addresses() {
+    // 接頭辞の無い名前は常にレジスタ
+    assert_eq!(Expr::parse("C"), Some(Expr::Register(Register::C)));
+    assert_eq!(Expr::parse("hl"), Some(Expr::Register(Register::HL)));
+    assert_eq!(Expr::parse("$C"), Some(Expr::Memory(0x000C)));
+    assert_eq!(Expr::parse("0xFF44"), Some(Expr::Memory(0xFF44)));
+    assert_eq!(Expr::parse("($FF44)"), Some(Expr::Memory(0xFF44)));
+    assert_eq!(Expr::parse("(DE)"), Some(Expr::Indirect(Register::DE)));
+    // 8bitレジスタでの間接参照と、接頭辞の無い数値は受け付けない
+    assert_eq!(Expr::parse("(A)"), None);
+    assert_eq!(Expr::parse("FF44"), None);
+    assert_eq!(Expr::parse("$"), None);
+}
+
+#[test]
+fn expressions_evaluate_registers_and_memory() {
+    let mut cpu = cpu();
+
+    let mut state = cpu.state();
+    state.a = 0x12;
+    state.bc = 0x3456;
+    state.hl = 0xC010;
+    cpu.set_state(state);
+    cpu.bus.write(0xC010, 0xAB).unwrap();
+    cpu.bus.write(0xC000, 0xCD).unwrap();
+
+    let eval = |s: &str| cpu.eval(&Expr::parse(s).unwrap());
+
+    assert_eq!(eval("A"), Some(0x12));
+    assert_eq!(eval("C"), Some(0x56));
+    assert_eq!(eval("BC"), Some(0x3456));
+    assert_eq!(eval("(HL)"), Some(0xAB));
+    assert_eq!(eval("$C000"), Some(0xCD));
+    assert_eq!(eval("(0xC000)"), Some(0xCD));
+
+    let cond = |s: &str| cpu.eval_condition(&Condition::parse(s).unwrap());
+
+    assert!(cond("A == 0x12"));
+    assert!(cond("A == 18"));
+    assert!(!cond("A != $12"));
+    assert!(cond("(HL) >= 0xAB"));
+    assert!(cond("BC < $3457"));
+    assert!(!cond("$C000 > 0xCD"));
+}
+
+#[test]
+fn trace_line_prints_bytes_as_two_digits_and_words_as_four() {
+    let mut cpu = cpu();
+
+    let mut state = cpu.state();
+    state.f = 0xB0;
+    state.bc = 0x0013;
+    state.de = 0xC000;
+    state.hl = 0x014D;
+    state.sp = 0xFFFE;
+    state.pc = 0x0101;
+    cpu.set_state(state);
+    cpu.bus.write(0xC000, 0x3C).unwrap();
+
+    let mnemonic = cpu.load_8_a_addr_de().unwrap();
+    assert_eq!(mnemonic, "LD A, (DE): (DE)=(C000)=3C");
+
+    assert_eq!(
+        cpu.trace_line(0x1A, &mnemonic),
+        "A: 3C F: B0 B: 00 C: 13 D: C0 E: 00 H: 01 L: 4D SP: FFFE PC: 0101 | 1A: LD A, (DE): (DE)=(C000)=3C"
+    );
+}
+
+const LD_B_B: [u8; 6] = [
+    0x06, 0x05, // LD B, $05
+    0x40, // LD B, B
+    0x04, // INC B
+    0x18, 0xFE, // JR -2
+];
+
+#[test]
+fn ld_b_b_triggers_the_software_break() {
+    let mut gb = boot(&rom(&LD_B_B));
+    gb.set_software_break(true);
+
+    // LD B, Bの直後で止まる
+    assert!(!gb.run_until(100_000, |gb| gb.pc() == 0x0104).unwrap());
+    assert_eq!(gb.pc(), 0x0103);
+
+    assert!(gb.software_break_hit());
+    // 取り出すとクリアされ、続きを実行できる
+    assert!(!gb.software_break_hit());
+    run_to(&mut gb, 0x0104);
+    // 命令自体の動作は変わらない
+    assert_eq!(gb.cpu_state().bc >> 8, 6);
+}
+
+#[test]
+fn ld_b_b_stops_run_cycles_early() {
+    let mut gb = boot(&rom(&LD_B_B));
+    gb.set_software_break(true);
+
+    let start = gb.cycles();
+    assert_eq!(gb.run_cycles(100_000).unwrap(), 0);
+
+    assert!(gb.cycles() - start < 100);
+    assert_eq!(gb.pc(), 0x0103);
+    assert!(gb.software_break_hit());
+
+    // 取り出した後は最後まで進む
+    let overshoot = gb.run_cycles(1000).unwrap();
+    assert!(gb.cycles() - start >= 1000 + overshoot);
+}
+
+#[test]
+fn ld_b_b_is_a_plain_load_when_the_option_is_off() {
+    let mut gb = boot(&rom(&LD_B_B));
+
+    run_to(&mut gb, 0x0104);
+
+    assert!(!gb.software_break_hit());
+    assert_eq!(gb.cpu_state().bc >> 8, 6);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::rom;
+use gb::disasm::{analyze, listing};
+
+// 0x0150のループから0x0170を呼ぶ。間の0x0155-0x016Fはどこからも実行されないデータ
+fn rom_with_data() -> Vec<u8> {
+    let mut data = rom(&[0xC3, 0x50, 0x01]); // JP $0150
+
+    #[rustfmt::skip]
+    data[0x0150..0x0155].copy_from_slice(&[
+        0xCD, 0x70, 0x01, // CALL $0170
+        0x18, 0xFB,       // JR $0150
+    ]);
+    // 線形に読むとLD A, $3Eが並ぶ
+    for b in &mut data[0x0155..0x0170] {
+        *b = 0x3E;
+    }
+    data[0x0170] = 0xC9; // RET
+
+    data
+}
+
+#[test]
+fn analyzer_follows_jumps_and_calls() {
+    let analysis = analyze(&rom_with_data());
+
+    for addr in [0x0100, 0x0150, 0x0153, 0x0170] {
+        assert!(analysis.instructions.contains_key(&addr), "{:#06X}", addr);
+    }
+    assert!(analysis.labels.contains(&0x0150));
+    assert!(analysis.labels.contains(&0x0170));
+}
+
+#[test]
+fn analyzer_leaves_the_data_blob_undisassembled() {
+    let data = rom_with_data();
+    let analysis = analyze(&data);
+
+    assert!(analysis.instructions.range(0x0155..0x0170).next().is_none());
+
+    let code = analysis.code_map(data.len());
+    assert!(code[0x0155..0x0170].iter().all(|&code| !code));
+    assert!(code[0x0150..0x0155].iter().all(|&code| code));
+
+    let listing = listing(&data);
+    assert!(listing.contains("    0155: db $3E, $3E"));
+    assert!(!listing.contains("LD A, $3E"));
+    assert!(listing.contains("L0170:\n    0170: RET"));
+}
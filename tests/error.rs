@@ -0,0 +1,44 @@
+mod common;
+
+use common::{boot, rom};
+use gb::error::GbError;
+use gb::rom::Rom;
+use std::error::Error;
+use std::io;
+
+#[test]
+fn wrapped_errors_are_the_source() {
+    let err = GbError::from(io::Error::new(io::ErrorKind::NotFound, "no rom"));
+
+    let source = err.source().expect("io error has a source");
+    assert_eq!(source.to_string(), "no rom");
+    assert!(source.downcast_ref::<io::Error>().is_some());
+}
+
+#[test]
+fn illegal_opcode_is_reported_with_the_opcode() {
+    let mut gb = boot(&rom(&[0xD3]));
+
+    assert!(matches!(
+        gb.run_cycles(4),
+        Err(GbError::IllegalOpcode(0xD3))
+    ));
+}
+
+#[test]
+fn truncated_rom_is_a_parse_error() {
+    assert!(matches!(
+        Rom::from_bytes(vec![0; 0x0100]),
+        Err(GbError::RomParse(_))
+    ));
+}
+
+#[test]
+fn unknown_palette_index_is_reported() {
+    let mut gb = boot(&rom(&[0x18, 0xFE]));
+
+    assert!(matches!(
+        gb.set_dmg_palette_by_index(1000),
+        Err(GbError::UnknownPalette(1000))
+    ));
+}
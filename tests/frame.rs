@@ -0,0 +1,74 @@
+mod common;
+
+use common::{boot, rom, run_to};
+use gb::error::GbError;
+use gb::gb::Gb;
+use gb::ppu::{DEFAULT_DMG_PALETTE, FRAME_SIZE, VISIBLE_WIDTH};
+use gb::CYCLES_PER_FRAME;
+
+const UNTOUCHED: u8 = 0x11;
+
+fn ly(gb: &Gb) -> u8 {
+    gb.io()[0x44]
+}
+
+fn tick_until(gb: &mut Gb, frame: &mut [u8], pred: impl Fn(&Gb) -> bool) {
+    let end = gb.cycles() + CYCLES_PER_FRAME;
+
+    while !pred(gb) {
+        assert!(gb.cycles() < end, "condition not reached within a frame");
+        gb.tick(frame).unwrap();
+    }
+}
+
+fn row(frame: &[u8], y: usize) -> &[u8] {
+    &frame[y * VISIBLE_WIDTH * 4..(y + 1) * VISIBLE_WIDTH * 4]
+}
+
+#[test]
+fn tick_writes_each_finished_line_into_the_slice() {
+    // LCDを止めてタイル0を色3で塗り、LCDを点け直す。BGは全てタイル0になる
+    let mut gb = boot(&rom(&[
+        0x3E, 0xE4, // LD A, $E4
+        0xE0, 0x47, // LDH ($47), A
+        0xAF, // XOR A
+        0xE0, 0x40, // LDH ($40), A
+        0x21, 0x00, 0x80, // LD HL, $8000
+        0x3E, 0xFF, // LD A, $FF
+        0x0E, 0x10, // LD C, $10
+        0x22, // LD (HL+), A
+        0x0D, // DEC C
+        0x20, 0xFC, // JR NZ, -4
+        0x3E, 0x91, // LD A, $91
+        0xE0, 0x40, // LDH ($40), A
+        0x18, 0xFE, // JR -2
+    ]));
+    run_to(&mut gb, 0x0116);
+
+    // 点け直した直後のフレームは表示されないため、次のフレームの先頭まで進める
+    let mut frame = vec![UNTOUCHED; FRAME_SIZE];
+    tick_until(&mut gb, &mut frame, |gb| ly(gb) == 153);
+    tick_until(&mut gb, &mut frame, |gb| ly(gb) == 0);
+    assert!(frame.iter().all(|&b| b == UNTOUCHED));
+
+    tick_until(&mut gb, &mut frame, |gb| ly(gb) == 1);
+
+    for pixel in row(&frame, 0).chunks(4) {
+        assert_eq!(pixel, DEFAULT_DMG_PALETTE[3]);
+    }
+    assert!(row(&frame, 1).iter().all(|&b| b == UNTOUCHED));
+}
+
+#[test]
+fn tick_rejects_a_wrongly_sized_slice() {
+    let mut gb = boot(&rom(&[0x18, 0xFE]));
+    let mut frame = vec![0; FRAME_SIZE - 4];
+
+    assert!(matches!(
+        gb.tick(&mut frame),
+        Err(GbError::InvalidFrameSize {
+            expected: FRAME_SIZE,
+            actual,
+        }) if actual == FRAME_SIZE - 4
+    ));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{boot, rom, run_to};
+
+// IE/IFにタイマー割り込みを立ててからHALTする
+const PENDING_TIMER: [u8; 6] = [
+    0x3E, 0x04, // LD A, $04
+    0xE0, 0xFF, // LDH ($FF), A
+    0xE0, 0x0F, // LDH ($0F), A
+];
+
+#[test]
+fn halt_bug_runs_next_instruction_twice() {
+    let mut program = vec![0xF3]; // DI
+    program.extend_from_slice(&PENDING_TIMER);
+    program.extend_from_slice(&[
+        0xAF, // XOR A
+        0x76, // HALT
+        0x3C, // INC A
+        0x18, 0xFE, // JR -2
+    ]);
+
+    let mut gb = boot(&rom(&program));
+    run_to(&mut gb, 0x010A);
+
+    assert_eq!(gb.cpu_state().a, 2);
+}
+
+#[test]
+fn halt_bug_reads_opcode_as_operand() {
+    let mut program = vec![0xF3]; // DI
+    program.extend_from_slice(&PENDING_TIMER);
+    program.extend_from_slice(&[
+        0x16, 0x00, // LD D, $00
+        0x76, // HALT
+        0x3E, 0x14, // LD A, $14 (HALTバグで LD A, $3E; INC D になる)
+        0x18, 0xFE, // JR -2
+    ]);
+
+    let mut gb = boot(&rom(&program));
+    run_to(&mut gb, 0x010C);
+
+    let state = gb.cpu_state();
+    assert_eq!(state.a, 0x3E);
+    assert_eq!(state.de >> 8, 1);
+}
+
+#[test]
+fn ei_halt_returns_to_halt() {
+    let mut program = vec![0xF3]; // DI
+    program.extend_from_slice(&PENDING_TIMER);
+    program.extend_from_slice(&[
+        0xFB, // EI
+        0x76, // HALT
+        0x18, 0xFE, // JR -2
+    ]);
+
+    let mut rom = rom(&program);
+    rom[0x0050..0x0056].copy_from_slice(&[
+        0x3E, 0x77, // LD A, $77
+        0xE1, // POP HL
+        0xE5, // PUSH HL
+        0xD9, // RETI
+        0x00,
+    ]);
+
+    let mut gb = boot(&rom);
+    run_to(&mut gb, 0x0054);
+    gb.run_cycles(100).unwrap();
+
+    let state = gb.cpu_state();
+    assert_eq!(state.a, 0x77);
+    // 戻り先はHALT自体
+    assert_eq!(state.hl, 0x0108);
+    assert!(state.halt);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{boot, rom};
+
+// NOP x3; SWAP A; JR -7 のループ
+const LOOP: [u8; 7] = [0x00, 0x00, 0x00, 0xCB, 0x37, 0x18, 0xF9];
+
+#[test]
+fn tight_loop_opcode_dominates_the_histogram() {
+    let mut gb = boot(&rom(&LOOP));
+    gb.set_opcode_histogram(true);
+
+    gb.run_cycles(10_000).unwrap();
+
+    let histogram = gb.opcode_histogram();
+    let (hottest, _) = histogram
+        .iter()
+        .enumerate()
+        .max_by_key(|&(_, &count)| count)
+        .unwrap();
+    assert_eq!(hottest, 0x00);
+
+    // 1周でNOPが3回、CBとSWAP AとJRが1回ずつ
+    let loops = histogram[0x18];
+    assert!(loops > 100);
+    assert!((histogram[0x00] as i64 - 3 * loops as i64).abs() <= 3);
+    assert!((histogram[0xCB] as i64 - loops as i64).abs() <= 1);
+    assert!((histogram[0x137] as i64 - loops as i64).abs() <= 1);
+
+    // CBプレフィックス付きの命令も1命令として数える
+    let base: u64 = histogram[..0x100].iter().sum();
+    assert_eq!(gb.total_instructions(), base);
+    assert_eq!(histogram[0x100..].iter().sum::<u64>(), histogram[0x137]);
+}
+
+#[test]
+fn histogram_is_empty_unless_enabled() {
+    let mut gb = boot(&rom(&LOOP));
+
+    gb.run_cycles(1000).unwrap();
+
+    assert!(gb.total_instructions() > 0);
+    assert!(gb.opcode_histogram().iter().all(|&count| count == 0));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{boot, rom, run_to};
+
+// DIでIE/IFにタイマー割り込みを立て、Bを0にする
+const PENDING_TIMER: [u8; 9] = [
+    0xF3, // DI
+    0x3E, 0x04, // LD A, $04
+    0xE0, 0xFF, // LDH ($FF), A
+    0xE0, 0x0F, // LDH ($0F), A
+    0x06, 0x00, // LD B, $00
+];
+
+// タイマー割り込みのハンドラで、その時点のBをAに写して止まる
+fn run_to_handler(program: &[u8]) -> u8 {
+    let mut data = rom(program);
+    data[0x0050..0x0053].copy_from_slice(&[
+        0x78, // LD A, B
+        0x18, 0xFE, // JR -2
+    ]);
+
+    let mut gb = boot(&data);
+    run_to(&mut gb, 0x0051);
+
+    gb.cpu_state().a
+}
+
+#[test]
+fn reti_services_an_interrupt_before_the_next_instruction() {
+    let mut program = PENDING_TIMER.to_vec();
+    program.extend_from_slice(&[
+        0xCD, 0x0F, 0x01, // CALL $010F
+        0x04, // INC B
+        0x18, 0xFE, // JR -2
+        0xD9, // $010F: RETI
+    ]);
+
+    assert_eq!(run_to_handler(&program), 0);
+}
+
+#[test]
+fn ei_services_an_interrupt_after_the_next_instruction() {
+    let mut program = PENDING_TIMER.to_vec();
+    program.extend_from_slice(&[
+        0xFB, // EI
+        0x04, // INC B
+        0x04, // INC B
+        0x18, 0xFE, // JR -2
+    ]);
+
+    assert_eq!(run_to_handler(&program), 1);
+}
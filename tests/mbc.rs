@@ -0,0 +1,294 @@
+mod common;
+
+use common::ROM_SIZE;
+use gb::mbc::{new_mbc, Mbc};
+use gb::rom::Rom;
+
+// MBC1+RAM、RAM 8KiB
+fn mbc1() -> Box<dyn Mbc + Send> {
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = 0x02;
+    data[0x0149] = 0x02;
+
+    new_mbc(Rom::from_bytes_unchecked(data).unwrap())
+}
+
+// MBC1+RAM+BATTERY、RAMサイズはヘッダの値
+fn mbc1_with_ram(ram_size: u8) -> Box<dyn Mbc + Send> {
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = 0x03;
+    data[0x0149] = ram_size;
+
+    let mut mbc = new_mbc(Rom::from_bytes_unchecked(data).unwrap());
+    mbc.write(0x0000, 0x0A).unwrap();
+
+    mbc
+}
+
+// MBC3+TIMER+RAM+BATTERY、RAM 32KiB (4バンク)
+fn mbc3() -> Box<dyn Mbc + Send> {
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = 0x10;
+    data[0x0149] = 0x03;
+
+    let mut mbc = new_mbc(Rom::from_bytes_unchecked(data).unwrap());
+    mbc.write(0x0000, 0x0A).unwrap();
+
+    mbc
+}
+
+fn latch(mbc: &mut Box<dyn Mbc + Send>) {
+    mbc.write(0x6000, 0x00).unwrap();
+    mbc.write(0x6000, 0x01).unwrap();
+}
+
+#[test]
+fn mbc1_ram_reads_open_bus_until_enabled() {
+    let mut mbc = mbc1();
+
+    // 無効の間は書き込みも無視される
+    mbc.write(0xA000, 0x12).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0xFF);
+    assert_eq!(mbc.ram()[0], 0x00);
+
+    mbc.write(0x0000, 0x0A).unwrap();
+    mbc.write(0xA000, 0x34).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x34);
+
+    // 下位4bitが0xA以外の値で再び無効になる
+    mbc.write(0x0000, 0x00).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0xFF);
+    assert_eq!(mbc.ram()[0], 0x34);
+}
+
+#[test]
+fn mbc1_2kib_ram_ends_at_0xa7ff_and_ignores_the_bank() {
+    let mut mbc = mbc1_with_ram(0x01);
+    assert_eq!(mbc.ram().len(), 0x800);
+
+    mbc.write(0xA000, 0x12).unwrap();
+    mbc.write(0xA7FF, 0x34).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x12);
+    assert_eq!(mbc.read(0xA7FF).unwrap(), 0x34);
+
+    // 2KiBを超える範囲はオープンバスで、書き込みも無視される
+    mbc.write(0xA800, 0x56).unwrap();
+    assert_eq!(mbc.read(0xA800).unwrap(), 0xFF);
+    assert_eq!(mbc.read(0xBFFF).unwrap(), 0xFF);
+
+    // モード1でRAMバンクを選んでも同じRAMが見える
+    mbc.write(0x4000, 0x01).unwrap();
+    mbc.write(0x6000, 0x01).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x12);
+    assert_eq!(mbc.read(0xA7FF).unwrap(), 0x34);
+}
+
+#[test]
+fn mbc1_32kib_ram_banks_are_selected_in_mode_1() {
+    let mut mbc = mbc1_with_ram(0x03);
+    assert_eq!(mbc.ram().len(), 0x8000);
+
+    // モード0では常にバンク0
+    mbc.write(0x4000, 0x02).unwrap();
+    mbc.write(0xA000, 0x10).unwrap();
+    assert_eq!(mbc.ram()[0x0000], 0x10);
+    assert_eq!(mbc.ram()[0x4000], 0x00);
+
+    // モードを切り替える前に書き込んだバンクもモード1で使われる。bit0以外は無視される
+    mbc.write(0x4000, 0x03).unwrap();
+    mbc.write(0x6000, 0x03).unwrap();
+    mbc.write(0xA000, 0x33).unwrap();
+    assert_eq!(mbc.ram()[0x6000], 0x33);
+
+    for bank in 0..4u8 {
+        mbc.write(0x4000, bank).unwrap();
+        mbc.write(0xA001, 0x20 + bank).unwrap();
+    }
+    for bank in 0..4u8 {
+        mbc.write(0x4000, bank).unwrap();
+        assert_eq!(mbc.read(0xA001).unwrap(), 0x20 + bank);
+        assert_eq!(mbc.ram()[bank as usize * 0x2000 + 1], 0x20 + bank);
+    }
+
+    // モード0に戻すとバンク0が見える
+    mbc.write(0x6000, 0x00).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x10);
+    assert_eq!(mbc.read(0xA001).unwrap(), 0x20);
+}
+
+#[test]
+fn mbc3_rtc_and_ram_are_selected_independently() {
+    let mut mbc = mbc3();
+
+    mbc.write(0x4000, 0x00).unwrap();
+    mbc.write(0xA000, 0x12).unwrap();
+    mbc.write(0x4000, 0x01).unwrap();
+    mbc.write(0xA000, 0x34).unwrap();
+
+    // 秒のレジスタは下位6bitのみ書き込める
+    mbc.write(0x4000, 0x08).unwrap();
+    mbc.write(0xA000, 0xFF).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x3F);
+
+    mbc.write(0x4000, 0x0B).unwrap();
+    mbc.write(0xA000, 0x56).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x56);
+
+    // RTCへの書き込みはRAMに影響しない
+    mbc.write(0x4000, 0x00).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x12);
+    mbc.write(0x4000, 0x01).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x34);
+    assert_eq!(mbc.ram()[0x0000], 0x12);
+    assert_eq!(mbc.ram()[0x2000], 0x34);
+
+    mbc.write(0x4000, 0x08).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x3F);
+
+    // 無効にするとRAMもRTCもオープンバスになる
+    mbc.write(0x0000, 0x00).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0xFF);
+    mbc.write(0x4000, 0x00).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0xFF);
+}
+
+#[test]
+fn mbc3_rtc_advances_with_cycles_and_reads_the_latched_value() {
+    let mut mbc = mbc3();
+
+    // 23:59:59、511日目から1秒進める
+    for (select, val) in [
+        (0x08, 59),
+        (0x09, 59),
+        (0x0A, 23),
+        (0x0B, 0xFF),
+        (0x0C, 0x01),
+    ] {
+        mbc.write(0x4000, select).unwrap();
+        mbc.write(0xA000, val).unwrap();
+    }
+
+    for _ in 0..4_194_304 {
+        mbc.tick();
+    }
+
+    // ラッチするまでは古い値が読める
+    mbc.write(0x4000, 0x08).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 59);
+
+    latch(&mut mbc);
+
+    for (select, val) in [(0x08, 0), (0x09, 0), (0x0A, 0), (0x0B, 0), (0x0C, 0x80)] {
+        mbc.write(0x4000, select).unwrap();
+        assert_eq!(mbc.read(0xA000).unwrap(), val, "register {:#04X}", select);
+    }
+}
+
+#[test]
+fn mbc3_rtc_does_not_advance_while_halted() {
+    let mut mbc = mbc3();
+
+    mbc.write(0x4000, 0x0C).unwrap();
+    mbc.write(0xA000, 0x40).unwrap();
+
+    for _ in 0..4_194_304 {
+        mbc.tick();
+    }
+
+    latch(&mut mbc);
+    mbc.write(0x4000, 0x08).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0);
+}
+
+// ヘッダ上は1MiB (64バンク) だが、実際には2バンクしか無いROM
+fn undersized(cartridge_type: u8) -> Box<dyn Mbc + Send> {
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = cartridge_type;
+    data[0x0148] = 0x05;
+
+    new_mbc(Rom::from_bytes_unchecked(data).unwrap())
+}
+
+#[test]
+fn undersized_rom_reads_open_bus_from_missing_banks() {
+    for cartridge_type in [0x01, 0x11] {
+        let mut mbc = undersized(cartridge_type);
+
+        mbc.write(0x2000, 0x1F).unwrap();
+        assert_eq!(mbc.read(0x4000).unwrap(), 0xFF);
+        assert_eq!(mbc.read(0x7FFF).unwrap(), 0xFF);
+
+        // 存在するバンクは読める
+        mbc.write(0x2000, 0x01).unwrap();
+        assert_eq!(mbc.read(0x4000).unwrap(), 0x00);
+    }
+}
+
+// 64KiB (4バンク) のROM。各バンクの先頭にバンク番号を書いておく
+fn four_banks(cartridge_type: u8) -> Box<dyn Mbc + Send> {
+    let mut data = vec![0; ROM_SIZE * 2];
+    data[0x0147] = cartridge_type;
+    data[0x0148] = 0x01;
+    for bank in 1..4 {
+        data[bank * 0x4000] = bank as u8;
+    }
+
+    new_mbc(Rom::from_bytes_unchecked(data).unwrap())
+}
+
+#[test]
+fn bank_numbers_wrap_at_the_rom_bank_count() {
+    for cartridge_type in [0x01, 0x11] {
+        let mut mbc = four_banks(cartridge_type);
+
+        for (select, bank) in [(0x02, 2), (0x05, 1), (0x07, 3), (0x1E, 2)] {
+            mbc.write(0x2000, select).unwrap();
+            assert_eq!(
+                mbc.read(0x4000).unwrap(),
+                bank,
+                "type {:#04X} select {:#04X}",
+                cartridge_type,
+                select
+            );
+        }
+    }
+}
+
+#[test]
+fn ram_bank_numbers_wrap_at_the_ram_bank_count() {
+    // MBC3+TIMER+RAM+BATTERY、RAM 8KiB (1バンク)
+    let mut data = vec![0; ROM_SIZE];
+    data[0x0147] = 0x10;
+    data[0x0149] = 0x02;
+    let mut mbc = new_mbc(Rom::from_bytes_unchecked(data).unwrap());
+    mbc.write(0x0000, 0x0A).unwrap();
+
+    mbc.write(0x4000, 0x00).unwrap();
+    mbc.write(0xA000, 0x12).unwrap();
+
+    mbc.write(0x4000, 0x03).unwrap();
+    assert_eq!(mbc.read(0xA000).unwrap(), 0x12);
+    mbc.write(0xA001, 0x34).unwrap();
+    assert_eq!(mbc.ram()[1], 0x34);
+}
+
+#[test]
+fn mbc1_bank2_supplies_the_high_rom_bank_bits() {
+    // MBC1、1MiB (64バンク)。各バンクの先頭にバンク番号を書いておく
+    let mut data = vec![0; 64 * 0x4000];
+    data[0x0147] = 0x01;
+    data[0x0148] = 0x05;
+    for bank in 1..64 {
+        data[bank * 0x4000] = bank as u8;
+    }
+    let mut mbc = new_mbc(Rom::from_bytes_unchecked(data).unwrap());
+
+    // BANK1とBANK2はどちらの順に書き込んでも組み合わされる
+    mbc.write(0x4000, 0x01).unwrap();
+    mbc.write(0x2000, 0x02).unwrap();
+    assert_eq!(mbc.read(0x4000).unwrap(), 0x22);
+
+    mbc.write(0x2000, 0x05).unwrap();
+    mbc.write(0x4000, 0x01).unwrap();
+    assert_eq!(mbc.read(0x4000).unwrap(), 0x25);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::rom;
+use gb::gb::{Gb, GbBuilder};
+use gb::utils::MemoryFill;
+
+fn build(fill: MemoryFill) -> Gb {
+    GbBuilder::new()
+        .strict(false)
+        .memory_fill(fill)
+        .build(&rom(&[0x18, 0xFE]))
+        .unwrap()
+}
+
+// WRAM、HRAM、VRAMを連結したもの
+fn memory(gb: &Gb) -> Vec<u8> {
+    let state = gb.snapshot();
+
+    [&state.bus.ram[..], &state.bus.hram[..], gb.vram(0)].concat()
+}
+
+#[test]
+fn same_seed_fills_memory_identically() {
+    let a = build(MemoryFill::Random(0x1234));
+    let b = build(MemoryFill::Random(0x1234));
+
+    let memory_a = memory(&a);
+
+    assert_eq!(a.memory_fill(), MemoryFill::Random(0x1234));
+    assert_eq!(memory_a, memory(&b));
+    // 疑似乱数で埋まっている
+    assert!(memory_a.iter().any(|&v| v != memory_a[0]));
+
+    let c = build(MemoryFill::Random(0x5678));
+    assert_ne!(memory_a, memory(&c));
+}
+
+#[test]
+fn default_and_pattern_fills() {
+    assert!(memory(&build(MemoryFill::default()))
+        .iter()
+        .all(|&v| v == 0));
+    assert!(memory(&build(MemoryFill::Pattern(0xA5)))
+        .iter()
+        .all(|&v| v == 0xA5));
+}
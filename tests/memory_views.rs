@@ -0,0 +1,37 @@
+mod common;
+
+use common::{boot, rom, run_to};
+
+#[test]
+fn vram_oam_and_io_views_reflect_writes() {
+    // LCDを止めてからVRAMとOAMに書き込む
+    let mut gb = boot(&rom(&[
+        0xAF, // XOR A
+        0xE0, 0x40, // LDH ($40), A
+        0x3E, 0x12, // LD A, $12
+        0xEA, 0x00, 0x80, // LD ($8000), A
+        0x3E, 0x34, // LD A, $34
+        0xEA, 0xFF, 0x9F, // LD ($9FFF), A
+        0x3E, 0x56, // LD A, $56
+        0xEA, 0x9F, 0xFE, // LD ($FE9F), A
+        0x3E, 0x07, // LD A, $07
+        0xE0, 0x43, // LDH ($43), A
+        0x18, 0xFE, // JR -2
+    ]));
+    run_to(&mut gb, 0x0116);
+
+    let vram = gb.vram(0);
+    assert_eq!(vram.len(), 0x2000);
+    assert_eq!(vram[0x0000], 0x12);
+    assert_eq!(vram[0x1FFF], 0x34);
+    // DMGにバンク1は無い
+    assert!(gb.vram(1).is_empty());
+
+    let oam = gb.oam();
+    assert_eq!(oam.len(), 0xA0);
+    assert_eq!(oam[0x9F], 0x56);
+
+    let io = gb.io();
+    assert_eq!(io[0x40], 0x00);
+    assert_eq!(io[0x43], 0x07);
+}
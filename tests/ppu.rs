@@ -0,0 +1,389 @@
+use gb::ppu::{
+    ColorCorrection, Layer, Ppu, DEFAULT_DMG_PALETTE, FRAME_SIZE, FULL_BG_SIZE, VISIBLE_WIDTH,
+};
+use gb::CYCLES_PER_FRAME;
+
+fn tick(ppu: &mut Ppu, cycles: u64) {
+    for _ in 0..cycles {
+        ppu.tick().unwrap();
+    }
+}
+
+#[test]
+fn lcd_off_holds_ly_and_raises_no_interrupts() {
+    let mut ppu = Ppu::new();
+    // 全てのSTAT割り込みを有効にしておく
+    ppu.write_lcd_status(0x78).unwrap();
+    tick(&mut ppu, 1000);
+
+    ppu.write_lcd_control(0x11).unwrap();
+    ppu.int_v_blank = false;
+    ppu.int_lcd_stat = false;
+    ppu.frame_ready = false;
+
+    tick(&mut ppu, CYCLES_PER_FRAME * 2);
+
+    assert_eq!(ppu.read_lines().unwrap(), 0);
+    assert_eq!(ppu.read_lcd_status().unwrap() & 0b11, 0);
+    assert!(!ppu.int_v_blank);
+    assert!(!ppu.int_lcd_stat);
+    assert!(!ppu.frame_ready);
+}
+
+#[test]
+fn first_frame_after_lcd_enable_is_skipped() {
+    let mut ppu = Ppu::new();
+    tick(&mut ppu, 1000);
+
+    ppu.write_lcd_control(0x11).unwrap();
+    ppu.write_lcd_control(0x91).unwrap();
+    ppu.frame_ready = false;
+
+    // 有効にした直後のフレームはVBlankに入っても表示されない
+    tick(&mut ppu, CYCLES_PER_FRAME);
+    assert!(ppu.int_v_blank);
+    assert!(!ppu.frame_ready);
+
+    tick(&mut ppu, CYCLES_PER_FRAME);
+    assert!(ppu.frame_ready);
+}
+
+// 1フレーム分進め、その間に揃ったラインを書き込んだ画面を返す
+fn render_frame(ppu: &mut Ppu) -> Vec<u8> {
+    let mut frame = vec![0; FRAME_SIZE];
+
+    for _ in 0..CYCLES_PER_FRAME {
+        ppu.tick().unwrap();
+        ppu.put_line(&mut frame).unwrap();
+    }
+
+    frame
+}
+
+#[test]
+fn snapshot_round_trip_renders_identically() {
+    let mut ppu = Ppu::new();
+
+    // タイル1を縞模様にし、BGにタイル0と1を交互に並べる
+    for i in 0..16 {
+        ppu.write(0x8010 + i, if i % 2 == 0 { 0x55 } else { 0x33 })
+            .unwrap();
+    }
+    for i in 0..0x400 {
+        ppu.write(0x9800 + i, (i % 2) as u8).unwrap();
+    }
+    ppu.write_bg_palette(0xE4).unwrap();
+    ppu.write_scroll_x(3).unwrap();
+    // フレームの途中の状態を保存する
+    tick(&mut ppu, 1000);
+
+    let state = ppu.snapshot();
+    let expected = render_frame(&mut ppu);
+
+    let mut colors = expected.chunks(4).collect::<Vec<_>>();
+    colors.sort();
+    colors.dedup();
+    assert!(colors.len() > 1);
+
+    ppu.write(0x8010, 0xFF).unwrap();
+    ppu.write_scroll_x(0x40).unwrap();
+    ppu.write_bg_palette(0x1B).unwrap();
+    tick(&mut ppu, 5000);
+
+    ppu.restore(&state).unwrap();
+    assert_eq!(ppu.snapshot(), state);
+    assert_eq!(render_frame(&mut ppu), expected);
+}
+
+fn pixel(frame: &[u8], x: usize, y: usize) -> &[u8] {
+    let offset = (y * VISIBLE_WIDTH + x) * 4;
+
+    &frame[offset..offset + 4]
+}
+
+// X=12-19にOBP0のスプライト (OAMの0番)、X=8-15にOBP1のスプライト (OAMの1番) を重ねる。
+// OBP0は色3、OBP1は色0、BGは色1になるようにしておく
+fn overlapping_sprites(opri: u8) -> Vec<u8> {
+    let mut ppu = Ppu::new();
+
+    for i in 0..16 {
+        ppu.write(0x8010 + i, 0xFF).unwrap();
+    }
+    for (i, &val) in [16, 20, 1, 0x00, 16, 16, 1, 0x10].iter().enumerate() {
+        ppu.write_oam(0xFE00 + i as u16, val).unwrap();
+    }
+    ppu.write_bg_palette(0x55).unwrap();
+    ppu.write_object_palette_0(0xFF).unwrap();
+    ppu.write_object_palette_1(0x00).unwrap();
+    ppu.write_lcd_control(0x93).unwrap();
+    ppu.write_object_priority_mode(opri).unwrap();
+
+    render_frame(&mut ppu)
+}
+
+#[test]
+fn opri_selects_sprite_priority_by_oam_index_or_x() {
+    // OPRI=1: DMGと同じくXが小さい方 (OBP1) が手前
+    let by_x = overlapping_sprites(1);
+    assert_eq!(pixel(&by_x, 13, 4), DEFAULT_DMG_PALETTE[0]);
+
+    // OPRI=0: OAMの順序が先の方 (OBP0) が手前
+    let by_index = overlapping_sprites(0);
+    assert_eq!(pixel(&by_index, 13, 4), DEFAULT_DMG_PALETTE[3]);
+
+    // 重なっていない部分はどちらでも同じ
+    for frame in [&by_x, &by_index] {
+        assert_eq!(pixel(frame, 9, 4), DEFAULT_DMG_PALETTE[0]);
+        assert_eq!(pixel(frame, 18, 4), DEFAULT_DMG_PALETTE[3]);
+        assert_eq!(pixel(frame, 30, 4), DEFAULT_DMG_PALETTE[1]);
+    }
+}
+
+#[test]
+fn gbc_lcd_correction_maps_known_rgb555_colors() {
+    // RGB555の(31, 0, 0)と(31, 31, 31)
+    let red = [0xF8, 0x00, 0x00, 0xFF];
+    let white = [0xF8, 0xF8, 0xF8, 0xFF];
+
+    assert_eq!(ColorCorrection::GbcLcd.apply(red), [201, 0, 46, 0xFF]);
+    assert_eq!(ColorCorrection::GbcLcd.apply(white), [240, 240, 240, 0xFF]);
+    assert_eq!(ColorCorrection::Grayscale.apply(red), [74, 74, 74, 0xFF]);
+    assert_eq!(ColorCorrection::None.apply(red), red);
+}
+
+#[test]
+fn color_correction_is_applied_to_the_rendered_frame() {
+    let mut ppu = Ppu::new();
+    ppu.write_bg_palette(0x00).unwrap();
+    ppu.set_color_correction(ColorCorrection::GbcLcd);
+
+    let frame = render_frame(&mut ppu);
+
+    let expected = ColorCorrection::GbcLcd.apply(DEFAULT_DMG_PALETTE[0]);
+    assert_ne!(expected, DEFAULT_DMG_PALETTE[0]);
+    assert_eq!(pixel(&frame, 0, 0), expected);
+    assert_eq!(pixel(&frame, 159, 143), expected);
+}
+
+// `cycle`サイクル目に`write`を呼びながら1フレーム描画する
+fn render_frame_with(ppu: &mut Ppu, cycle: u64, write: impl FnOnce(&mut Ppu)) -> Vec<u8> {
+    let mut frame = vec![0; FRAME_SIZE];
+    let mut write = Some(write);
+
+    for i in 0..CYCLES_PER_FRAME {
+        if i == cycle {
+            write.take().unwrap()(ppu);
+        }
+        ppu.tick().unwrap();
+        ppu.put_line(&mut frame).unwrap();
+    }
+
+    frame
+}
+
+// BGの左半分 (0-15列目) をタイル0 (色0)、右半分をタイル1 (色3) にする
+fn split_background() -> Ppu {
+    let mut ppu = Ppu::new();
+
+    for i in 0..16 {
+        ppu.write(0x8010 + i, 0xFF).unwrap();
+    }
+    for i in 0..0x400 {
+        ppu.write(0x9800 + i, (i % 32 >= 16) as u8).unwrap();
+    }
+    ppu.write_bg_palette(0xE4).unwrap();
+
+    ppu
+}
+
+// 0ライン目のモード3の途中 (OAMスキャン80ドットの後、さらに80ドット)
+const MID_LINE: u64 = 160;
+
+#[test]
+fn mid_line_scx_write_takes_effect_from_the_current_dot() {
+    let mut ppu = split_background();
+
+    let frame = render_frame_with(&mut ppu, MID_LINE, |ppu| ppu.write_scroll_x(128).unwrap());
+
+    // 0ライン目の左側は書き込み前のSCX=0、右側はSCX=128で描画される
+    assert_eq!(pixel(&frame, 4, 0), DEFAULT_DMG_PALETTE[0]);
+    assert_eq!(pixel(&frame, 100, 0), DEFAULT_DMG_PALETTE[3]);
+
+    // 次のラインからは全体がSCX=128
+    assert_eq!(pixel(&frame, 4, 1), DEFAULT_DMG_PALETTE[3]);
+}
+
+#[test]
+fn mid_line_bgp_write_takes_effect_from_the_current_dot() {
+    let mut ppu = split_background();
+    ppu.write_scroll_x(128).unwrap();
+
+    let frame = render_frame_with(&mut ppu, MID_LINE, |ppu| {
+        ppu.write_bg_palette(0x24).unwrap()
+    });
+
+    assert_eq!(pixel(&frame, 4, 0), DEFAULT_DMG_PALETTE[3]);
+    assert_eq!(pixel(&frame, 100, 0), DEFAULT_DMG_PALETTE[0]);
+    assert_eq!(pixel(&frame, 4, 1), DEFAULT_DMG_PALETTE[0]);
+}
+
+// X=12-19、Y=0-7に色3のスプライトを1つ置き、BGは色1にする
+fn sprite_scene() -> Ppu {
+    let mut ppu = Ppu::new();
+
+    for i in 0..16 {
+        ppu.write(0x8010 + i, 0xFF).unwrap();
+    }
+    for (i, &val) in [16, 20, 1, 0x00].iter().enumerate() {
+        ppu.write_oam(0xFE00 + i as u16, val).unwrap();
+    }
+    ppu.write_bg_palette(0x55).unwrap();
+    ppu.write_object_palette_0(0xFF).unwrap();
+    ppu.write_lcd_control(0x93).unwrap();
+
+    ppu
+}
+
+#[test]
+fn force_sprites_off_renders_only_the_background() {
+    let with_sprites = render_frame(&mut sprite_scene());
+    assert_eq!(pixel(&with_sprites, 13, 4), DEFAULT_DMG_PALETTE[3]);
+
+    let mut ppu = sprite_scene();
+    assert!(!ppu.toggle_layer(Layer::Sprites));
+    assert!(ppu.force_sprites_off);
+    let frame = render_frame(&mut ppu);
+
+    // LCDCはそのままで、BGだけが描画される
+    assert_eq!(ppu.read_lcd_control().unwrap(), 0x93);
+    assert!(frame.chunks(4).all(|pixel| pixel == DEFAULT_DMG_PALETTE[1]));
+}
+
+const VIEWPORT: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+
+fn full_bg(scx: u8, scy: u8) -> Vec<u8> {
+    let mut ppu = Ppu::new();
+    ppu.write_bg_palette(0xE4).unwrap();
+    ppu.write_scroll_x(scx).unwrap();
+    ppu.write_scroll_y(scy).unwrap();
+
+    let mut out = vec![0; FULL_BG_SIZE];
+    ppu.render_full_bg(&mut out).unwrap();
+
+    out
+}
+
+fn full_pixel(out: &[u8], x: usize, y: usize) -> &[u8] {
+    let offset = (y * 256 + x) * 4;
+
+    &out[offset..offset + 4]
+}
+
+#[test]
+fn full_bg_draws_the_viewport_at_scx_scy() {
+    let out = full_bg(40, 30);
+
+    for &(x, y) in &[
+        (40, 30),
+        (199, 30),
+        (40, 173),
+        (199, 173),
+        (100, 30),
+        (40, 100),
+    ] {
+        assert_eq!(full_pixel(&out, x, y), VIEWPORT, "({}, {})", x, y);
+    }
+    for &(x, y) in &[(39, 30), (40, 29), (41, 31), (200, 173), (100, 100)] {
+        assert_ne!(full_pixel(&out, x, y), VIEWPORT, "({}, {})", x, y);
+    }
+}
+
+#[test]
+fn full_bg_viewport_wraps_around_the_map() {
+    // 右端と下端は256x256の端で折り返す
+    let out = full_bg(200, 180);
+
+    for &(x, y) in &[
+        (200, 180),
+        (103, 180),
+        (200, 67),
+        (103, 67),
+        (255, 180),
+        (0, 180),
+    ] {
+        assert_eq!(full_pixel(&out, x, y), VIEWPORT, "({}, {})", x, y);
+    }
+    assert_ne!(full_pixel(&out, 150, 100), VIEWPORT);
+}
+
+#[test]
+fn tile_rows_decode_low_and_high_bit_planes() {
+    // 0x3C/0x7Eの行は左から 0, 2, 3, 3, 3, 3, 2, 0
+    let expected = [0, 2, 3, 3, 3, 3, 2, 0];
+
+    // 0x8000からのタイルデータと、0x9000を0番とする符号付きのタイルデータ
+    for &(lcdc, addr) in &[(0x91, 0x8000), (0x81, 0x9000)] {
+        let mut ppu = Ppu::new();
+        ppu.write(addr, 0x3C).unwrap();
+        ppu.write(addr + 1, 0x7E).unwrap();
+        ppu.write_bg_palette(0xE4).unwrap();
+        ppu.write_lcd_control(lcdc).unwrap();
+
+        let frame = render_frame(&mut ppu);
+
+        for (x, &index) in expected.iter().enumerate() {
+            assert_eq!(
+                pixel(&frame, x, 0),
+                DEFAULT_DMG_PALETTE[index],
+                "LCDC {:#04X} x={}",
+                lcdc,
+                x
+            );
+            // 全てのタイルが0番なので8ピクセルごとに繰り返す
+            assert_eq!(pixel(&frame, x + 8, 0), DEFAULT_DMG_PALETTE[index]);
+        }
+    }
+}
+
+// Y=0-15、X=72-79に8x16のスプライトを置き (タイル2と3は色3、タイル4は透明)、BGは色1にする。
+// タイル番号を3にしておき、8x8として読むと10ライン目はタイル4の行になるようにする
+fn tall_sprite_scene(lcdc: u8) -> Ppu {
+    let mut ppu = Ppu::new();
+
+    for i in 0..32 {
+        ppu.write(0x8020 + i, 0xFF).unwrap();
+    }
+    for (i, &val) in [16, 80, 3, 0x00].iter().enumerate() {
+        ppu.write_oam(0xFE00 + i as u16, val).unwrap();
+    }
+    ppu.write_bg_palette(0x55).unwrap();
+    ppu.write_object_palette_0(0xFF).unwrap();
+    ppu.write_lcd_control(lcdc).unwrap();
+
+    ppu
+}
+
+// 10ライン目のOAMスキャンが終わった後、スプライトを描画する前
+const LINE_10_AFTER_OAM_SCAN: u64 = 10 * 456 + 84;
+
+#[test]
+fn sprite_height_is_latched_at_oam_scan() {
+    // 8x16で選ばれたスプライトは、描画前に8x8に切り替えても下半分が描画される
+    let mut ppu = tall_sprite_scene(0x97);
+    let frame = render_frame_with(&mut ppu, LINE_10_AFTER_OAM_SCAN, |ppu| {
+        ppu.write_lcd_control(0x93).unwrap()
+    });
+    assert_eq!(pixel(&frame, 75, 9), DEFAULT_DMG_PALETTE[3]);
+    assert_eq!(pixel(&frame, 75, 10), DEFAULT_DMG_PALETTE[3]);
+    // 次のラインからは8x8で選ばれる
+    assert_eq!(pixel(&frame, 75, 11), DEFAULT_DMG_PALETTE[1]);
+
+    // 8x8で選ばれなかったスプライトは、描画前に8x16に切り替えても描画されない
+    let mut ppu = tall_sprite_scene(0x93);
+    let frame = render_frame_with(&mut ppu, LINE_10_AFTER_OAM_SCAN, |ppu| {
+        ppu.write_lcd_control(0x97).unwrap()
+    });
+    assert_eq!(pixel(&frame, 75, 9), DEFAULT_DMG_PALETTE[1]);
+    assert_eq!(pixel(&frame, 75, 10), DEFAULT_DMG_PALETTE[1]);
+    assert_eq!(pixel(&frame, 75, 11), DEFAULT_DMG_PALETTE[3]);
+}
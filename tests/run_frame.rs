@@ -0,0 +1,34 @@
+mod common;
+
+use common::valid_rom;
+use gb::gb::{Gb, GbBuilder};
+use gb::ppu::FRAME_SIZE;
+use gb::rom::Rom;
+use gb::CYCLES_PER_FRAME;
+use rustyline::Editor;
+
+// 既定ではデバッガのREPLでブロックせずに1フレーム進む
+#[test]
+fn new_gb_runs_a_frame_without_the_debugger() {
+    let rom = Rom::from_bytes(valid_rom()).unwrap();
+    let mut gb = Gb::new(rom, Editor::new());
+    gb.reset().unwrap();
+    assert!(!gb.debugger_enabled());
+
+    let frame = gb.run_frame().unwrap();
+    assert_eq!(frame.pixels.len(), FRAME_SIZE);
+
+    assert!(gb.cycles() > 0 && gb.cycles() <= CYCLES_PER_FRAME + 24);
+}
+
+#[test]
+fn default_built_gb_runs_a_frame_without_the_debugger() {
+    let mut gb = GbBuilder::new().build(&valid_rom()).unwrap();
+    gb.reset().unwrap();
+    assert!(!gb.debugger_enabled());
+
+    let frame = gb.run_frame().unwrap();
+    assert_eq!(frame.pixels.len(), FRAME_SIZE);
+
+    assert!(gb.cycles() > 0 && gb.cycles() <= CYCLES_PER_FRAME + 24);
+}
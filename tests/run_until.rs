@@ -0,0 +1,31 @@
+mod common;
+
+use common::{boot, rom};
+
+fn jump_to_0150() -> Vec<u8> {
+    let mut data = rom(&[
+        0x00, // NOP
+        0xC3, 0x50, 0x01, // JP $0150
+    ]);
+    data[0x0150..0x0152].copy_from_slice(&[0x18, 0xFE]); // JR -2
+
+    data
+}
+
+#[test]
+fn run_until_stops_at_a_known_pc() {
+    let mut gb = boot(&jump_to_0150());
+
+    assert!(gb.run_until(1000, |gb| gb.pc() == 0x0150).unwrap());
+    assert_eq!(gb.pc(), 0x0150);
+    // NOP (4) + JP (16) 以内に止まる
+    assert!(gb.cycles() <= 20);
+}
+
+#[test]
+fn run_until_gives_up_after_the_budget() {
+    let mut gb = boot(&jump_to_0150());
+
+    assert!(!gb.run_until(1000, |gb| gb.pc() == 0x0200).unwrap());
+    assert!(gb.cycles() >= 1000 && gb.cycles() < 1000 + 16);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{bus_with_rom, ROM_SIZE};
+use gb::bus::Bus;
+use gb::cpu::Cpu;
+use rustyline::Editor;
+
+const IF_STAT: u8 = 1 << 1;
+const IF_TIMER: u8 = 1 << 2;
+
+// LY=LYC=2でSTAT割り込みを要求するバス。
+// 割り込みハンドラはベクタの下位バイトを(DE)に記録してRETIする
+fn stat_bus() -> Bus {
+    let mut data = vec![0; ROM_SIZE];
+    for vector in [0x48, 0x50] {
+        // LD A, vector; LD (DE), A; INC DE; RETI
+        data[vector..vector + 5].copy_from_slice(&[0x3E, vector as u8, 0x12, 0x13, 0xD9]);
+    }
+
+    let mut bus = bus_with_rom(data);
+
+    bus.write(0xFF45, 2).unwrap();
+    bus.write(0xFF41, 0x40).unwrap();
+    bus.write(0xFF0F, 0x00).unwrap();
+
+    bus
+}
+
+fn irq(bus: &Bus) -> u8 {
+    bus.read(0xFF0F).unwrap() & 0x1F
+}
+
+// STAT割り込みが要求されるまでのサイクル数
+fn stat_cycle() -> u64 {
+    let mut bus = stat_bus();
+
+    (1..)
+        .find(|_| {
+            bus.tick().unwrap();
+            irq(&bus) & IF_STAT != 0
+        })
+        .unwrap()
+}
+
+#[test]
+fn timer_and_stat_on_the_same_cycle_are_serviced_in_priority_order() {
+    let stat = stat_cycle();
+
+    // 262144Hz (16サイクル毎) で、TMAの再ロードはオーバーフローの4サイクル後。
+    // DIVをリセットするサイクルをずらして、再ロードがSTATと同じサイクルになるようにする
+    let reset = (stat - 4) % 16;
+    let increments = (stat - 4 - reset) / 16;
+
+    let mut bus = stat_bus();
+    for _ in 0..reset {
+        bus.tick().unwrap();
+    }
+    bus.write(0xFF04, 0).unwrap();
+    bus.write(0xFF05, (0x100 - increments) as u8).unwrap();
+    bus.write(0xFF07, 0x05).unwrap();
+
+    for _ in reset..stat - 1 {
+        bus.tick().unwrap();
+    }
+    assert_eq!(irq(&bus), 0);
+
+    bus.tick().unwrap();
+    assert_eq!(irq(&bus), IF_STAT | IF_TIMER);
+
+    // 0xC000でループしている最中に割り込みを受け付ける
+    bus.write(0xC000, 0x18).unwrap(); // JR -2
+    bus.write(0xC001, 0xFE).unwrap();
+    bus.write(0xFFFF, IF_STAT | IF_TIMER).unwrap();
+
+    let mut cpu = Cpu::new(bus, Editor::new());
+    let mut state = cpu.state();
+    state.pc = 0xC000;
+    state.sp = 0xDFF0;
+    state.de = 0xC100;
+    state.ime = true;
+    cpu.set_state(state);
+
+    for _ in 0..200 {
+        cpu.tick().unwrap();
+    }
+
+    // STATが先に処理され、RETIの後にタイマーが処理される
+    assert_eq!(cpu.bus.read(0xC100).unwrap(), 0x48);
+    assert_eq!(cpu.bus.read(0xC101).unwrap(), 0x50);
+    assert_eq!(cpu.state().de, 0xC102);
+}
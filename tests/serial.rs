@@ -0,0 +1,90 @@
+mod common;
+
+use common::{boot, bus, rom, run_to};
+use gb::serial::SerialSink;
+use std::sync::{Arc, Mutex};
+
+const IF_SERIAL: u8 = 1 << 3;
+
+#[test]
+fn sb_reads_back_the_written_value() {
+    let mut bus = bus();
+
+    bus.write(0xFF01, 0x42).unwrap();
+
+    assert_eq!(bus.read(0xFF01).unwrap(), 0x42);
+}
+
+#[test]
+fn transfer_without_a_partner_shifts_in_ones() {
+    let mut bus = bus();
+    bus.write(0xFF0F, 0x00).unwrap();
+    bus.write(0xFF01, 0x42).unwrap();
+    bus.write(0xFF02, 0x81).unwrap();
+
+    // 転送中は受信中の1が読める
+    assert_eq!(bus.read(0xFF01).unwrap(), 0xFF);
+    assert_eq!(bus.read(0xFF02).unwrap(), 0xFF);
+    assert_eq!(bus.read(0xFF0F).unwrap() & IF_SERIAL, 0);
+
+    // 8192Hzで8ビット
+    for _ in 0..8 * 512 {
+        bus.tick().unwrap();
+    }
+
+    assert_eq!(bus.read(0xFF01).unwrap(), 0xFF);
+    assert_eq!(bus.read(0xFF02).unwrap(), 0x7F);
+    assert_eq!(bus.read(0xFF0F).unwrap() & IF_SERIAL, IF_SERIAL);
+}
+
+// 0x0120からの文字列を1バイトずつ送信し、終わったら0x0115で止まる
+fn send_hello() -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut data = rom(&[
+        0x21, 0x20, 0x01, // LD HL, $0120
+        0x2A,             // LD A, (HL+)
+        0xB7,             // OR A
+        0x28, 0x0E,       // JR Z, $0115
+        0xE0, 0x01,       // LDH ($01), A
+        0x3E, 0x81,       // LD A, $81
+        0xE0, 0x02,       // LDH ($02), A
+        0xF0, 0x02,       // LDH A, ($02)
+        0xCB, 0x7F,       // BIT 7, A
+        0x20, 0xFA,       // JR NZ, $010D
+        0x18, 0xEE,       // JR $0103
+        0x18, 0xFE,       // JR $0115
+    ]);
+    data[0x0120..0x0124].copy_from_slice(b"Hi!\0");
+
+    data
+}
+
+#[derive(Default, Clone)]
+struct Counter(Arc<Mutex<Vec<u8>>>);
+
+impl SerialSink for Counter {
+    fn on_byte(&mut self, b: u8) {
+        self.0.lock().unwrap().push(b);
+    }
+}
+
+#[test]
+fn custom_sink_receives_every_byte() {
+    let counter = Counter::default();
+
+    let mut gb = boot(&send_hello());
+    gb.set_serial_sink(Box::new(counter.clone()));
+    run_to(&mut gb, 0x0115);
+
+    assert_eq!(*counter.0.lock().unwrap(), b"Hi!");
+    // Sinkを設定すると既定のバッファには溜まらない
+    assert_eq!(gb.serial_output(), "");
+}
+
+#[test]
+fn default_sink_buffers_into_serial_output() {
+    let mut gb = boot(&send_hello());
+    run_to(&mut gb, 0x0115);
+
+    assert_eq!(gb.serial_output(), "Hi!");
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::bus;
+use gb::bus::Bus;
+
+fn tick(bus: &mut Bus, cycles: u64) {
+    for _ in 0..cycles {
+        bus.tick().unwrap();
+    }
+}
+
+fn tima(bus: &Bus) -> u8 {
+    bus.read(0xFF05).unwrap()
+}
+
+#[test]
+fn div_write_ticks_tima_while_the_selected_bit_is_high() {
+    let mut bus = bus();
+    // 4096Hzではカウンタのbit9の立ち下がりでTIMAが進む
+    bus.write(0xFF07, 0x04).unwrap();
+
+    // bit9が立つまで進める (DIV=0x02)
+    tick(&mut bus, 0x200);
+    assert_eq!(bus.read(0xFF04).unwrap(), 0x02);
+    assert_eq!(tima(&bus), 0);
+
+    bus.write(0xFF04, 0x00).unwrap();
+    assert_eq!(bus.read(0xFF04).unwrap(), 0x00);
+    assert_eq!(tima(&bus), 1);
+
+    // bit9が立っていなければ進まない
+    tick(&mut bus, 0x100);
+    bus.write(0xFF04, 0x00).unwrap();
+    assert_eq!(tima(&bus), 1);
+}
+
+#[test]
+fn div_write_can_overflow_tima() {
+    let mut bus = bus();
+    bus.write(0xFF06, 0xAB).unwrap();
+    bus.write(0xFF05, 0xFF).unwrap();
+    bus.write(0xFF07, 0x04).unwrap();
+    bus.write(0xFF0F, 0x00).unwrap();
+
+    tick(&mut bus, 0x200);
+    bus.write(0xFF04, 0x00).unwrap();
+    tick(&mut bus, 4);
+
+    assert_eq!(tima(&bus), 0xAB);
+    assert_eq!(bus.read(0xFF0F).unwrap() & 0x04, 0x04);
+}
+
+// 262144Hzで、次のインクリメントでオーバーフローする状態にする
+fn about_to_overflow() -> Bus {
+    let mut bus = bus();
+    bus.write(0xFF06, 0xAB).unwrap();
+    bus.write(0xFF05, 0xFF).unwrap();
+    bus.write(0xFF07, 0x05).unwrap();
+    bus.write(0xFF0F, 0x00).unwrap();
+
+    bus
+}
+
+fn tick_until_overflow(bus: &mut Bus) {
+    for _ in 0..16 {
+        bus.tick().unwrap();
+        if tima(bus) == 0 {
+            return;
+        }
+    }
+
+    panic!("TIMA did not overflow");
+}
+
+#[test]
+fn tima_reloads_tma_one_m_cycle_after_overflow() {
+    let mut bus = about_to_overflow();
+    tick_until_overflow(&mut bus);
+
+    // 1Mサイクルの間はTIMAが0のままで、割り込みも要求されない
+    for _ in 0..3 {
+        assert_eq!(tima(&bus), 0);
+        assert_eq!(bus.read(0xFF0F).unwrap() & 0x04, 0);
+        bus.tick().unwrap();
+    }
+    assert_eq!(tima(&bus), 0);
+
+    bus.tick().unwrap();
+    assert_eq!(tima(&bus), 0xAB);
+    assert_eq!(bus.read(0xFF0F).unwrap() & 0x04, 0x04);
+}
+
+#[test]
+fn tima_write_during_the_reload_delay_cancels_the_reload() {
+    let mut bus = about_to_overflow();
+    tick_until_overflow(&mut bus);
+
+    bus.tick().unwrap();
+    bus.write(0xFF05, 0x12).unwrap();
+    tick(&mut bus, 8);
+
+    assert_eq!(tima(&bus), 0x12);
+    assert_eq!(bus.read(0xFF0F).unwrap() & 0x04, 0);
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use common::{boot, rom, run_to};
+use gb::gb::Gb;
+use gb::CYCLES_PER_FRAME;
+
+const CYCLES_PER_LINE: u64 = 456;
+
+fn ly(gb: &Gb) -> u8 {
+    gb.io()[0x44]
+}
+
+// LCDをオンにしてからループし、LYが0になった直後まで進める
+fn boot_at_line_0() -> Gb {
+    boot_at_line_0_with(&[])
+}
+
+// `routine`を0x0150に置いておく
+fn boot_at_line_0_with(routine: &[u8]) -> Gb {
+    let mut data = rom(&[
+        0x3E, 0x91, // LD A, $91
+        0xE0, 0x40, // LDH ($40), A
+        0x18, 0xFE, // JR -2
+    ]);
+    data[0x0150..0x0150 + routine.len()].copy_from_slice(routine);
+
+    let mut gb = boot(&data);
+
+    run_to(&mut gb, 0x0104);
+    assert!(gb.run_until(CYCLES_PER_FRAME, |gb| ly(gb) == 153).unwrap());
+    assert!(gb.run_until(CYCLES_PER_FRAME, |gb| ly(gb) == 0).unwrap());
+
+    gb
+}
+
+#[test]
+fn nop_and_memory_read_cycles() {
+    let mut gb = boot(&rom(&[
+        0x00, // NOP
+        0x7E, // LD A, (HL)
+        0x18, 0xFE, // JR -2
+    ]));
+
+    let start = gb.cycles();
+    assert_eq!(gb.run_cycles(1).unwrap(), 3);
+    assert_eq!(gb.cycles() - start, 4);
+
+    let start = gb.cycles();
+    assert_eq!(gb.run_cycles(1).unwrap(), 7);
+    assert_eq!(gb.cycles() - start, 8);
+}
+
+#[test]
+fn one_frame_wraps_ly_once() {
+    let mut gb = boot_at_line_0();
+    let mut wraps = 0;
+    let mut prev = ly(&gb);
+    let end = gb.cycles() + CYCLES_PER_FRAME;
+
+    while gb.cycles() < end {
+        gb.run_cycles(1).unwrap();
+
+        let cur = ly(&gb);
+        if cur < prev {
+            wraps += 1;
+        }
+        prev = cur;
+    }
+
+    assert_eq!(wraps, 1);
+    assert_eq!(ly(&gb), 0);
+}
+
+#[test]
+fn run_cycles_counts_t_cycles() {
+    let mut gb = boot_at_line_0();
+    let start = gb.cycles();
+
+    let overshoot = gb.run_cycles(CYCLES_PER_LINE * 10).unwrap();
+
+    assert_eq!(gb.cycles() - start, CYCLES_PER_LINE * 10 + overshoot);
+    assert!(overshoot < 16);
+    assert_eq!(ly(&gb), 10);
+}
+
+// 0x0100の命令1つにかかるTサイクル数
+fn instruction_cycles(program: &[u8]) -> u64 {
+    let mut gb = boot(&rom(program));
+
+    gb.run_cycles(1).unwrap() + 1
+}
+
+fn assert_cycles(matrix: &[(&str, &[u8], u64)]) {
+    for &(name, program, cycles) in matrix {
+        assert_eq!(instruction_cycles(program), cycles, "{}", name);
+    }
+}
+
+#[test]
+fn jump_and_stack_pointer_cycles() {
+    assert_cycles(&[
+        ("NOP", &[0x00], 4),
+        ("JP HL", &[0xE9], 4),
+        ("LD SP, HL", &[0xF9], 8),
+    ]);
+}
+
+#[test]
+fn sixteen_bit_inc_and_dec_cycles() {
+    assert_cycles(&[
+        ("INC BC", &[0x03], 8),
+        ("DEC DE", &[0x1B], 8),
+        ("INC HL", &[0x23], 8),
+        ("DEC SP", &[0x3B], 8),
+    ]);
+}
+
+#[test]
+fn cb_rotate_cycles() {
+    assert_cycles(&[
+        ("RLC B", &[0xCB, 0x00], 8),
+        ("RRC C", &[0xCB, 0x09], 8),
+        ("RL D", &[0xCB, 0x12], 8),
+        ("RR A", &[0xCB, 0x1F], 8),
+        ("RLC (HL)", &[0xCB, 0x06], 16),
+        ("RRC (HL)", &[0xCB, 0x0E], 16),
+        ("RL (HL)", &[0xCB, 0x16], 16),
+        ("RR (HL)", &[0xCB, 0x1E], 16),
+    ]);
+}
+
+#[test]
+fn logical_and_compare_cycles() {
+    assert_cycles(&[
+        ("AND B", &[0xA0], 4),
+        ("XOR C", &[0xA9], 4),
+        ("OR D", &[0xB2], 4),
+        ("CP E", &[0xBB], 4),
+        ("AND (HL)", &[0xA6], 8),
+        ("XOR (HL)", &[0xAE], 8),
+        ("OR (HL)", &[0xB6], 8),
+        ("CP (HL)", &[0xBE], 8),
+        ("AND n", &[0xE6, 0x0F], 8),
+        ("XOR n", &[0xEE, 0x0F], 8),
+        ("OR n", &[0xF6, 0x0F], 8),
+        ("CP n", &[0xFE, 0x0F], 8),
+    ]);
+}
+
+// 次のラインの先頭 (モード2の開始) にHALTで合わせ、`nops`個のNOPの後にSTATを読む
+fn stat_after_nops(nops: usize) -> u8 {
+    #[rustfmt::skip]
+    let mut routine = vec![
+        0xF3,       // DI
+        0x3E, 0x20, // LD A, $20
+        0xE0, 0x41, // LDH ($41), A
+        0x3E, 0x02, // LD A, $02
+        0xE0, 0xFF, // LDH ($FF), A
+        0xAF,       // XOR A
+        0xE0, 0x0F, // LDH ($0F), A
+        0x76,       // HALT
+    ];
+    routine.resize(routine.len() + nops, 0x00);
+    routine.extend_from_slice(&[
+        0xF0, 0x41, // LDH A, ($41)
+        0x18, 0xFE, // JR -2
+    ]);
+    let end = 0x0150 + routine.len() as u16 - 2;
+
+    let mut gb = boot_at_line_0_with(&routine);
+    let mut state = gb.cpu_state();
+    state.pc = 0x0150;
+    gb.set_cpu_state(&state);
+    run_to(&mut gb, end);
+
+    assert_eq!(ly(&gb), 1);
+
+    gb.cpu_state().a
+}
+
+#[test]
+fn stat_read_sees_the_mode_at_the_dot_of_the_access() {
+    // LDHの読み込みは命令の3Mサイクル目。NOPが16個ならモード3に入る (ドット80) 直前、
+    // 17個なら直後にSTATを読む
+    assert_eq!(stat_after_nops(16) & 0b11, 2);
+    assert_eq!(stat_after_nops(17) & 0b11, 3);
+}